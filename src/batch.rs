@@ -0,0 +1,242 @@
+use crate::load_protocol_from;
+use crate::protocol::types::Protocol;
+use crate::replay::types::ReplayError;
+use crate::replay::Replay;
+use rayon::prelude::*;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+const REPLAY_EXTENSION: &str = "SC2Replay";
+
+/// Protocol of a base build, loaded by the first thread needing it, or the message of the error
+/// that loading it failed with.
+type ProtocolSlot = Arc<OnceLock<Result<Arc<Protocol>, String>>>;
+
+/// Protocols shared by the decoding threads, loaded once per base build.
+pub struct ProtocolCache {
+    directory: PathBuf,
+    header_build: u32,
+    protocols: Mutex<HashMap<u32, ProtocolSlot>>,
+}
+
+impl ProtocolCache {
+    /// Cache of the `protocol{base_build}.py` files of `directory`, decoding the replay headers
+    /// with the protocol of `header_build`, which only needs to describe the header of every
+    /// replay, like the latest protocol does.
+    pub fn new(directory: impl Into<PathBuf>, header_build: u32) -> Self {
+        ProtocolCache {
+            directory: directory.into(),
+            header_build,
+            protocols: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Protocol of `base_build`, loading it on first use.
+    ///
+    /// The map is only locked to find the slot of the build: the protocol is parsed outside the
+    /// lock, so threads needing other builds are not held up, while threads needing the same build
+    /// wait for a single parse.
+    pub fn get(&self, base_build: u32) -> Result<Arc<Protocol>, ReplayError> {
+        let slot = self
+            .protocols
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .entry(base_build)
+            .or_default()
+            .clone();
+        slot.get_or_init(|| {
+            load_protocol_from(&self.directory, base_build)
+                .map(Arc::new)
+                .map_err(|error| match error {
+                    ReplayError::Protocol(message) => message,
+                    error => error.to_string(),
+                })
+        })
+        .clone()
+        .map_err(ReplayError::Protocol)
+    }
+}
+
+/// Outcome of decoding a replay of a batch, with the error message of a failed decoding.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchResult {
+    pub path: PathBuf,
+    pub result: Result<Value, String>,
+}
+
+impl BatchResult {
+    pub fn to_json(&self) -> Value {
+        match &self.result {
+            Ok(value) => json!({ "path": self.path, "replay": value }),
+            Err(error) => json!({ "path": self.path, "error": error }),
+        }
+    }
+}
+
+/// Replays of a directory, or paths matching a glob pattern like `replays/**/*.SC2Replay`,
+/// in path order.
+pub fn replay_paths(directory_or_pattern: &str) -> Result<Vec<PathBuf>, glob::PatternError> {
+    let path = Path::new(directory_or_pattern);
+    let mut paths: Vec<PathBuf> = if path.is_dir() {
+        path.read_dir()
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| Some(entry.ok()?.path()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    } else {
+        glob::glob(directory_or_pattern)?
+            .filter_map(Result::ok)
+            .collect()
+    };
+    paths.retain(|path| {
+        path.is_file()
+            && path
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case(REPLAY_EXTENSION))
+    });
+    paths.sort();
+    Ok(paths)
+}
+
+/// Decodes the replays of `paths` in parallel with the protocols of their builds.
+///
+/// A replay failing to open or decode is reported in its result without stopping the batch.
+/// Results keep the order of `paths`.
+pub fn decode_batch<F>(paths: &[PathBuf], protocols: &ProtocolCache, decode: F) -> Vec<BatchResult>
+where
+    F: Fn(&mut Replay) -> Result<Value, ReplayError> + Sync,
+{
+    paths
+        .par_iter()
+        .map(|path| BatchResult {
+            path: path.clone(),
+            result: decode_replay(path, protocols, &decode).map_err(|error| error.to_string()),
+        })
+        .collect()
+}
+
+/// Opens the replay at `path` once, reading its base build from the header before decoding it
+/// with the protocol of that build.
+fn decode_replay<F>(
+    path: &Path,
+    protocols: &ProtocolCache,
+    decode: &F,
+) -> Result<Value, ReplayError>
+where
+    F: Fn(&mut Replay) -> Result<Value, ReplayError>,
+{
+    let header_protocol = protocols.get(protocols.header_build)?;
+    let mut replay = Replay::open(path, &header_protocol)?;
+    let base_build = replay.try_user_data()?.version.base_build;
+    let protocol = protocols.get(base_build)?;
+    decode(&mut replay.with_protocol(&protocol))
+}
+
+/// Writes one JSON object per line for each result.
+pub fn write_jsonl<W: Write>(results: &[BatchResult], mut writer: W) -> io::Result<()> {
+    for result in results {
+        serde_json::to_writer(&mut writer, &result.to_json())?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
+
+/// Default output of a batch: build, length, map and players of the replay with the winner.
+pub fn summary(replay: &mut Replay) -> Result<Value, ReplayError> {
+    let user_data = replay.try_user_data()?;
+    let details = replay.try_details()?;
+    let winner = replay.try_winner()?;
+    let players: Vec<Value> = details
+        .players
+        .iter()
+        .enumerate()
+        .map(|(index, player)| {
+            json!({
+                "player_id": index + 1,
+                "name": player.name,
+                "race": player.race,
+                "team_id": player.team_id,
+                "result": format!("{:?}", player.result),
+            })
+        })
+        .collect();
+    Ok(json!({
+        "base_build": user_data.version.base_build,
+        "game_loops": user_data.elapsed_game_loops,
+        "title": details.title,
+        "players": players,
+        "winner": winner.map(|winner| winner.player_ids),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_loads_each_protocol_once_with_no_error() {
+        let protocols = ProtocolCache::new("assets/protocols", 93272);
+        let loaded: Vec<Arc<Protocol>> = (0..4)
+            .into_par_iter()
+            .map(|_| protocols.get(93272).unwrap())
+            .collect();
+        assert!(loaded
+            .iter()
+            .all(|protocol| Arc::ptr_eq(protocol, &loaded[0])));
+        assert!(matches!(protocols.get(1), Err(ReplayError::Protocol(_))));
+        assert!(matches!(protocols.get(1), Err(ReplayError::Protocol(_))));
+    }
+
+    #[test]
+    fn it_decodes_batch_with_no_error() {
+        let mut paths = replay_paths("assets/replays/*.SC2Replay").unwrap();
+        assert_eq!(paths, replay_paths("assets/replays").unwrap());
+        paths.push(PathBuf::from("assets/replays/missing.SC2Replay"));
+
+        let protocols = ProtocolCache::new("assets/protocols", 93272);
+        let results = decode_batch(&paths, &protocols, summary);
+        assert_eq!(results.len(), paths.len());
+        let decoded = &results[0].result.as_ref().unwrap();
+        assert_eq!(decoded["base_build"], 93272);
+        assert_eq!(decoded["winner"], json!([1]));
+        assert!(results.last().unwrap().result.is_err());
+
+        let mut output = Vec::new();
+        write_jsonl(&results, &mut output).unwrap();
+        let lines: Vec<Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), paths.len());
+        assert!(lines.last().unwrap()["error"].is_string());
+    }
+
+    #[test]
+    fn it_reports_replays_missing_fields_without_stopping_the_batch() {
+        let directory = std::env::temp_dir().join(format!("s2prot-batch-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let protocol = std::fs::read_to_string("assets/protocols/protocol93272.py").unwrap();
+        std::fs::write(
+            directory.join("protocol93272.py"),
+            protocol.replace("('m_toon',22,1)", "('m_toonRenamed',22,1)"),
+        )
+        .unwrap();
+
+        let paths = replay_paths("assets/replays").unwrap();
+        let protocols = ProtocolCache::new(&directory, 93272);
+        let results = decode_batch(&paths, &protocols, summary);
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(
+            results[0].result,
+            Err(String::from(
+                "Failed to decode replay.details: Missing field: m_toon"
+            ))
+        );
+    }
+}
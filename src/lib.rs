@@ -1,14 +1,11 @@
+#[cfg(feature = "parallel")]
+pub mod batch;
 pub mod protocol;
 pub mod replay;
 use std::path::Path;
 
-pub mod bit_packed_buff {
-    pub fn read_bits() {
-        println!("hello");
-    }
-}
-
 use protocol::types::Protocol;
+use replay::types::ReplayError;
 use std::fs;
 
 pub fn load_protocol_version(version: &str) -> Protocol {
@@ -21,8 +18,20 @@ pub fn load_protocol_version(version: &str) -> Protocol {
     protocol
 }
 
-pub fn load_replay_file(file_name: &str, protocol: &Protocol) {
-    let file_path = format!("assets\\replays\\{}", file_name);
-    let replay = replay::build_replay(&file_path, protocol);
-    println!("{:?}", replay);
+/// Loads the protocol of `base_build` from the `protocol{base_build}.py` file of `directory`.
+pub fn load_protocol_from(directory: &Path, base_build: u32) -> Result<Protocol, ReplayError> {
+    let file_path = directory.join(format!("protocol{base_build}.py"));
+    let content = fs::read_to_string(&file_path).map_err(|error| {
+        ReplayError::Protocol(format!("Failed to read {}: {error}", file_path.display()))
+    })?;
+    let (_, protocol) = protocol::build_protocol(content.as_str()).map_err(|_| {
+        ReplayError::Protocol(format!("Failed to build protocol {}", file_path.display()))
+    })?;
+
+    Ok(protocol)
+}
+
+pub fn load_replay_file<'p>(file_name: &str, protocol: &'p Protocol) -> replay::Replay<'p> {
+    let file_path = Path::new("assets/replays").join(file_name);
+    replay::build_replay(file_path.to_str().unwrap(), protocol)
 }
@@ -107,7 +107,7 @@ fn build_type_info(input: &str) -> IResult<&str, TypeInfo> {
                 })
                 .collect();
             let (input, _) = skip_remaining_of_line(input)?;
-            fields.sort_by(|f1, f2| f1.tag.cmp(&f2.tag));
+            fields.sort_by_key(|f| f.tag);
             (input, TypeInfo::Struct { fields })
         }
         "_fourcc" => {
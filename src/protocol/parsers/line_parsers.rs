@@ -16,6 +16,7 @@ pub fn parse_constant(input: &str) -> IResult<&str, usize> {
     Ok((input, constant))
 }
 
+#[allow(dead_code)]
 pub fn parse_comments(input: &str) -> IResult<&str, &str> {
     let (input, _) = many0(line_ending)(input)?;
     let (input, _) = tag("#")(input)?;
@@ -25,6 +26,7 @@ pub fn parse_comments(input: &str) -> IResult<&str, &str> {
     Ok((input, comment.trim_end()))
 }
 
+#[allow(dead_code)]
 pub fn parse_imports(input: &str) -> IResult<&str, (&str, &str)> {
     let (input, import_from) = preceded(tag("from "), take_until(" import "))(input)?;
     let (input, imported) = preceded(tag(" import "), take_until("\n"))(input)?;
@@ -32,6 +34,7 @@ pub fn parse_imports(input: &str) -> IResult<&str, (&str, &str)> {
     Ok((input, (import_from, imported.trim_end())))
 }
 
+#[allow(dead_code)]
 pub fn parse_blank_lines(input: &str) -> IResult<&str, &str> {
     let (input, _) = many0(line_ending)(input)?;
 
@@ -1,210 +1,846 @@
+pub mod apm;
+pub mod battlelobby;
 pub mod buffer;
+pub mod build_order;
+pub mod camera;
+pub mod chat;
+pub mod commands;
 pub mod decoders;
+pub mod details;
+pub mod game_metadata;
+pub mod game_time;
+pub mod init_data;
+pub mod presence;
+pub mod selection;
+pub mod stats;
+pub mod timeline;
 pub mod types;
+pub mod units;
+pub mod visitor;
 
-use crate::protocol::types::{EventType, Protocol, TypeInfo};
-use buffer::BitPackedBuff;
-use decoders::{raw_decode, versioned_decode};
+use crate::protocol::types::{EventType, Protocol};
+use apm::PlayerApm;
+use battlelobby::BattleLobby;
+use build_order::BuildOrderItem;
+use camera::{CameraMetrics, CameraPosition};
+use chat::{ChatMessage, Ping};
+use commands::{AbilityTable, Command};
+use decoders::{BitPackedDecoder, DecodeError, Decoder, VersionedDecoder};
+use details::Details;
+use game_metadata::GameMetadata;
+use game_time::{GameClock, GameSpeed};
+use init_data::InitData;
 use mpq::Archive;
+use presence::{PresenceEvent, Winner};
+use selection::SelectionTracker;
 use serde_json::Value;
+use stats::PlayerStats;
 use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
 use std::str;
+use timeline::Timeline;
 use types::*;
+use units::UnitTracker;
 
-pub fn build_replay(file_name: &str, protocol: &Protocol) -> Vec<ParsedField> {
-    let mut archive = load_mpq_archive(file_name);
-    // let parsed_user_data = decode_user_data(&mut archive, protocol);
-    list_files_in_archive(&mut archive);
-    // let parsed_details_data = decode_details_data(&mut archive, protocol);
-    // let parsed_init_data = decode_init_data(&mut archive, protocol);
-    // let parse_game_events = decode_game_events_data(&mut archive, protocol);
-    // let parsed_message_events = decode_message_events_data(&mut archive, protocol);
-    let parsed_tracker_events = decode_tracker_events_data(&mut archive, protocol);
+/// Handle on a replay archive whose files are decoded on demand with `protocol`.
+#[derive(Debug)]
+pub struct Replay<'p> {
+    archive: Archive,
+    protocol: &'p Protocol,
+    /// Clock of the replay, built on first use.
+    clock: Option<GameClock>,
+}
+
+impl<'p> Replay<'p> {
+    /// Opens the replay at `path`, to be decoded with `protocol`.
+    pub fn open(path: impl AsRef<Path>, protocol: &'p Protocol) -> Result<Self, ReplayError> {
+        let archive = Archive::load(fs::read(path)?)?;
+        Ok(Replay {
+            archive,
+            protocol,
+            clock: None,
+        })
+    }
+
+    /// The same replay decoded with `protocol`, e.g. the protocol of its base build once read
+    /// from the header, without opening the archive again.
+    pub fn with_protocol(self, protocol: &Protocol) -> Replay<'_> {
+        Replay {
+            archive: self.archive,
+            protocol,
+            clock: None,
+        }
+    }
+}
+
+impl Replay<'_> {
+    pub fn user_data(&mut self) -> UserData {
+        self.try_user_data().expect("Failed to decode user data")
+    }
+
+    pub fn try_user_data(&mut self) -> Result<UserData, ReplayError> {
+        let user_data = decode_user_data(&mut self.archive, self.protocol)?;
+        match user_data.value {
+            Some(ref value) if value.field("m_version").is_some() => Ok(UserData::from(value)),
+            _ => Err(ReplayError::Decode {
+                file: USER_DATA,
+                error: DecodeError::MissingField("m_version"),
+            }),
+        }
+    }
+
+    pub fn details(&mut self) -> Details {
+        self.try_details().expect("Failed to decode details")
+    }
+
+    pub fn try_details(&mut self) -> Result<Details, ReplayError> {
+        let details = decode_details_data(&mut self.archive, self.protocol)?;
+        details
+            .value
+            .as_ref()
+            .ok_or(DecodeError::MissingField("DetailsData"))
+            .and_then(Details::try_from)
+            .map_err(|error| ReplayError::Decode {
+                file: DETAILS,
+                error,
+            })
+    }
+
+    pub fn init_data(&mut self) -> InitData {
+        self.try_init_data().expect("Failed to decode init data")
+    }
+
+    pub fn try_init_data(&mut self) -> Result<InitData, ReplayError> {
+        let init_data = decode_init_data(&mut self.archive, self.protocol)?;
+        init_data
+            .value
+            .as_ref()
+            .ok_or(DecodeError::MissingField("InitData"))
+            .and_then(InitData::try_from)
+            .map_err(|error| ReplayError::Decode {
+                file: INIT_DATA,
+                error,
+            })
+    }
+
+    /// Returns `None` for older replays which do not contain `replay.gamemetadata.json`, and an
+    /// error when the JSON is malformed.
+    pub fn game_metadata(&mut self) -> Result<Option<GameMetadata>, serde_json::Error> {
+        decode_game_metadata_json(&mut self.archive)?
+            .map(serde_json::from_value)
+            .transpose()
+    }
+
+    /// Lists the files stored in the archive with their uncompressed sizes, failing when the
+    /// archive has no `(listfile)`.
+    pub fn files(&mut self) -> Result<Vec<ArchiveFile>, ReplayError> {
+        Ok(list_files_in_archive(&mut self.archive)?
+            .into_iter()
+            .filter_map(|name| {
+                let size = self.archive.open_file(&name).ok()?.size();
+                Some(ArchiveFile { name, size })
+            })
+            .collect())
+    }
+
+    /// Returns the raw bytes of any file of the archive, e.g. `replay.sync.events`.
+    pub fn read_raw(&mut self, name: &str) -> Option<Vec<u8>> {
+        read_archive_file(&mut self.archive, name)
+    }
+
+    /// Returns `None` for archives which do not contain `replay.server.battlelobby`.
+    pub fn battle_lobby(&mut self) -> Option<BattleLobby> {
+        self.read_raw("replay.server.battlelobby")
+            .map(|data| BattleLobby::from_bytes(&data))
+    }
+
+    pub fn game_events(&mut self) -> Vec<Event> {
+        self.try_game_events()
+            .expect("Failed to decode game events")
+    }
+
+    pub fn try_game_events(&mut self) -> Result<Vec<Event>, ReplayError> {
+        decode_game_events_data(&mut self.archive, self.protocol)
+    }
+
+    pub fn message_events(&mut self) -> Vec<Event> {
+        self.try_message_events()
+            .expect("Failed to decode message events")
+    }
+
+    pub fn try_message_events(&mut self) -> Result<Vec<Event>, ReplayError> {
+        decode_message_events_data(&mut self.archive, self.protocol)
+    }
+
+    pub fn tracker_events(&mut self) -> Vec<Event> {
+        self.try_tracker_events()
+            .expect("Failed to decode tracker events")
+    }
+
+    pub fn try_tracker_events(&mut self) -> Result<Vec<Event>, ReplayError> {
+        decode_tracker_events_data(&mut self.archive, self.protocol)
+    }
+
+    /// Game, message and tracker events merged in game loop order.
+    pub fn timeline(&mut self) -> Timeline {
+        Timeline::new(
+            self.game_events(),
+            self.message_events(),
+            self.tracker_events(),
+        )
+    }
+
+    /// Typed `SCmdEvent`s of every user.
+    pub fn commands(&mut self) -> Vec<Command> {
+        commands::commands(&self.game_events())
+    }
+
+    /// Clock converting the game loops of the events to times, at the game speed of the lobby
+    /// and the speed changes of the game events.
+    ///
+    /// The clock is built once per replay, only decoding the game events changing the speed.
+    pub fn clock(&mut self) -> GameClock {
+        self.try_clock().expect("Failed to build game clock")
+    }
+
+    pub fn try_clock(&mut self) -> Result<GameClock, ReplayError> {
+        if let Some(clock) = &self.clock {
+            return Ok(clock.clone());
+        }
+        let filter = EventFilter {
+            names: vec![game_time::SET_GAME_SPEED_EVENT.to_string()],
+            ..Default::default()
+        };
+        let speed_changes = self.filtered_events(EventTypeVariant::GameEvent, &filter)?;
+        let clock = GameClock::from_events(
+            GameSpeed::from(self.try_init_data()?.game_description.game_speed as isize),
+            self.try_user_data()?.use_scaled_time,
+            &speed_changes,
+        );
+        Ok(self.clock.insert(clock).clone())
+    }
+
+    /// Selections and control groups of every user after `game_loop`.
+    pub fn selection_at(&mut self, game_loop: usize) -> SelectionTracker {
+        SelectionTracker::until(&self.game_events(), game_loop)
+    }
+
+    pub fn camera_track(&mut self, user_id: u8) -> Vec<CameraPosition> {
+        camera::camera_track(&self.game_events(), user_id)
+    }
+
+    /// Camera metrics of `user_id` until they left, with their starting town hall as home.
+    pub fn camera_metrics(&mut self, user_id: u8) -> CameraMetrics {
+        let user_data = self.user_data();
+        let player_id = self.init_data().player_id(user_id, &self.details());
+        let units = self.units();
+        let home = player_id
+            .and_then(|player_id| units.starting_town_hall(player_id))
+            .and_then(|town_hall| town_hall.positions.first())
+            .map(|position| (position.x as f64, position.y as f64));
+        let events = self.game_events();
+        let end_loop = apm::game_end_loop(&events, &[user_id])
+            .unwrap_or(user_data.elapsed_game_loops as usize);
+        camera::camera_metrics(&events, user_id, home, end_loop, &self.clock())
+    }
+
+    /// Chat messages with the names of their senders.
+    pub fn chat(&mut self) -> Vec<ChatMessage> {
+        let names = chat::user_names(&self.details(), &self.init_data());
+        chat::chat(&self.message_events(), &names, &self.clock())
+    }
+
+    /// Minimap pings with the names of their senders.
+    pub fn pings(&mut self) -> Vec<Ping> {
+        let names = chat::user_names(&self.details(), &self.init_data());
+        chat::pings(&self.message_events(), &names, &self.clock())
+    }
+
+    /// Users joining and leaving the game.
+    pub fn presence_timeline(&mut self) -> Vec<PresenceEvent> {
+        self.try_presence_timeline()
+            .expect("Failed to decode game events")
+    }
+
+    pub fn try_presence_timeline(&mut self) -> Result<Vec<PresenceEvent>, ReplayError> {
+        let filter = EventFilter {
+            names: presence::PRESENCE_EVENTS.map(String::from).to_vec(),
+            ..Default::default()
+        };
+        let events = self.filtered_events(EventTypeVariant::GameEvent, &filter)?;
+        Ok(presence::presence_timeline(&events))
+    }
+
+    /// Winning players, inferred from the leave order when the replay records no result.
+    pub fn winner(&mut self) -> Option<Winner> {
+        self.try_winner().expect("Failed to decode winner")
+    }
+
+    pub fn try_winner(&mut self) -> Result<Option<Winner>, ReplayError> {
+        let details = self.try_details()?;
+        let metadata = self.game_metadata().ok().flatten();
+        let init_data = self.try_init_data()?;
+        let timeline = self.try_presence_timeline()?;
+        Ok(presence::winner(
+            &details,
+            metadata.as_ref(),
+            &init_data,
+            &timeline,
+        ))
+    }
+
+    /// History of every unit, rebuilt from the tracker events.
+    pub fn units(&mut self) -> UnitTracker {
+        UnitTracker::from_events(&self.tracker_events())
+    }
+
+    /// Economy of `player_id` every 10 game seconds, from the `SPlayerStatsEvent`s.
+    pub fn stats_timeline(&mut self, player_id: u8) -> Vec<PlayerStats> {
+        stats::stats_timeline(&self.tracker_events(), player_id)
+    }
+
+    /// Structures, units and upgrades of `player_id`, see `build_order::build_order`.
+    pub fn build_order(&mut self, player_id: u8) -> Vec<BuildOrderItem> {
+        self.build_order_with_abilities(player_id, None)
+    }
+
+    /// Same as `build_order`, telling when units were queued from the ability names of
+    /// `abilities`.
+    pub fn build_order_with_abilities(
+        &mut self,
+        player_id: u8,
+        abilities: Option<&dyn AbilityTable>,
+    ) -> Vec<BuildOrderItem> {
+        let details = self.details();
+        let init_data = self.init_data();
+        let user_ids: Vec<u8> = init_data
+            .lobby_state
+            .slots
+            .iter()
+            .filter_map(|slot| slot.user_id)
+            .filter(|&user_id| init_data.player_id(user_id, &details) == Some(player_id))
+            .collect();
+        let commands: Vec<Command> = self
+            .commands()
+            .into_iter()
+            .filter(|command| user_ids.contains(&command.user_id))
+            .collect();
+        build_order::build_order(
+            &self.tracker_events(),
+            &commands,
+            player_id,
+            &self.clock(),
+            abilities,
+        )
+    }
+
+    /// APM and EPM of every participant, in windows of `apm::DEFAULT_WINDOW_SECONDS`, up to the
+    /// first participant leaving the game.
+    pub fn apm(&mut self) -> Vec<PlayerApm> {
+        let user_data = self.user_data();
+        let user_ids: Vec<u8> = self
+            .init_data()
+            .lobby_state
+            .slots
+            .iter()
+            .filter(|slot| !slot.is_observer())
+            .filter_map(|slot| slot.user_id)
+            .collect();
+        let events = self.game_events();
+        let end_loop =
+            apm::game_end_loop(&events, &user_ids).unwrap_or(user_data.elapsed_game_loops as usize);
+        let clock = self.clock();
+
+        user_ids
+            .iter()
+            .map(|&user_id| {
+                apm::player_apm(
+                    &events,
+                    user_id,
+                    end_loop,
+                    &clock,
+                    apm::DEFAULT_WINDOW_SECONDS,
+                )
+            })
+            .collect()
+    }
+
+    /// Events of a stream selected by `filter`, skipping the others while decoding.
+    ///
+    /// Fails for the sync and smartcam streams, which can only be read raw.
+    pub fn filtered_events(
+        &mut self,
+        variant: EventTypeVariant,
+        filter: &EventFilter,
+    ) -> Result<Vec<Event>, ReplayError> {
+        decode_events_data_for_variant(&mut self.archive, self.protocol, variant, filter)
+    }
+
+    /// Returns the undecoded sync stream, `None` when the archive does not contain it.
+    pub fn sync_events(&mut self) -> Option<Vec<u8>> {
+        decode_sync_events_data(&mut self.archive)
+    }
 
-    // println!(
-    //     "parsed game metadata: {:?}",
-    //     decode_game_metadata_json(&mut archive)
-    // );
-    vec![]
+    /// Returns the undecoded smartcam stream, `None` when the archive does not contain it.
+    pub fn smartcam_events(&mut self) -> Option<Vec<u8>> {
+        decode_smartcam_events_data(&mut self.archive)
+    }
 }
 
-fn load_mpq_archive(file_name: &str) -> Archive {
-    Archive::open(file_name).expect("Failed to open MPQ archive")
+/// Name of the MPQ user data header in errors, as it is not an archive file.
+const USER_DATA: &str = "(user data)";
+const DETAILS: &str = "replay.details";
+const INIT_DATA: &str = "replay.initdata";
+
+pub fn build_replay<'p>(file_name: &str, protocol: &'p Protocol) -> Replay<'p> {
+    Replay::open(file_name, protocol).expect("Failed to open MPQ archive")
 }
 
-fn decode_user_data(archive: &mut Archive, protocol: &Protocol) -> ParsedField {
+pub fn decode_user_data(
+    archive: &mut Archive,
+    protocol: &Protocol,
+) -> Result<ParsedField, ReplayError> {
     let user_data = archive
-        .read_user_data()
-        .expect("Failed to read User Data")
-        .expect("Failed to unwrap User Data");
+        .read_user_data()?
+        .ok_or(ReplayError::MissingFile(USER_DATA))?;
     let index: usize = protocol.replay_header_type_index.unwrap();
-    let mut buffer = BitPackedBuff::new_big_endian(&user_data);
+    let mut decoder = VersionedDecoder::new(&user_data, protocol);
 
-    versioned_decode("UserData", index, protocol, &mut buffer)
+    Ok(ParsedField {
+        name: String::from("UserData"),
+        value: decode_file(&mut decoder, index, USER_DATA)?,
+    })
 }
 
-fn list_files_in_archive(archive: &mut Archive) {
-    let listfile = archive.open_file("(listfile)").unwrap();
-    let mut buf: Vec<u8> = vec![0; listfile.size() as usize];
-    listfile.read(archive, &mut buf).unwrap();
+fn decode_file<D: Decoder>(
+    decoder: &mut D,
+    type_index: usize,
+    file: &'static str,
+) -> Result<Option<ParsedFieldType>, ReplayError> {
+    decoder
+        .try_instance(type_index)
+        .map_err(|error| ReplayError::Decode { file, error })
+}
 
-    print!("{}", str::from_utf8(&buf).unwrap());
+/// Names of the files of the archive, read from its `(listfile)`.
+pub fn list_files_in_archive(archive: &mut Archive) -> Result<Vec<String>, ReplayError> {
+    const LISTFILE: &str = "(listfile)";
+    let listfile =
+        read_archive_file(archive, LISTFILE).ok_or(ReplayError::MissingFile(LISTFILE))?;
+
+    Ok(String::from_utf8_lossy(&listfile)
+        .lines()
+        .map(String::from)
+        .collect())
 }
 
-fn decode_details_data(archive: &mut Archive, protocol: &Protocol) -> ParsedField {
-    let details_data_file = archive
-        .open_file("replay.details")
-        .expect("Failed to open replay.details file");
-    let mut details_data: Vec<u8> = vec![0; details_data_file.size() as usize];
-    details_data_file.read(archive, &mut details_data).unwrap();
+/// Returns the decompressed content of the file `name`, or `None` if the archive does not
+/// contain it.
+pub fn read_archive_file(archive: &mut Archive, name: &str) -> Option<Vec<u8>> {
+    let file = archive.open_file(name).ok()?;
+    let mut buf: Vec<u8> = vec![0; file.size() as usize];
+    file.read(archive, &mut buf).ok()?;
+
+    Some(buf)
+}
+
+pub fn decode_details_data(
+    archive: &mut Archive,
+    protocol: &Protocol,
+) -> Result<ParsedField, ReplayError> {
+    let details_data =
+        read_archive_file(archive, DETAILS).ok_or(ReplayError::MissingFile(DETAILS))?;
 
     let index: usize = protocol.game_details_type_index.unwrap();
-    let mut buffer = BitPackedBuff::new_big_endian(&details_data);
+    let mut decoder = VersionedDecoder::new(&details_data, protocol);
 
-    versioned_decode("DetailsData", index, protocol, &mut buffer)
+    Ok(ParsedField {
+        name: String::from("DetailsData"),
+        value: decode_file(&mut decoder, index, DETAILS)?,
+    })
 }
 
-fn decode_init_data(archive: &mut Archive, protocol: &Protocol) -> ParsedField {
-    let init_data_file = archive
-        .open_file("replay.initdata")
-        .expect("Failed to open replay.initdata file");
-    let mut init_data: Vec<u8> = vec![0; init_data_file.size() as usize];
-    init_data_file
-        .read(archive, &mut init_data)
-        .expect("Failed to read init data");
+pub fn decode_init_data(
+    archive: &mut Archive,
+    protocol: &Protocol,
+) -> Result<ParsedField, ReplayError> {
+    let init_data =
+        read_archive_file(archive, INIT_DATA).ok_or(ReplayError::MissingFile(INIT_DATA))?;
 
     let index: usize = protocol.replay_initdata_type_index.unwrap();
-    let mut buffer = BitPackedBuff::new_big_endian(&init_data);
+    let mut decoder = BitPackedDecoder::new(&init_data, protocol);
 
-    raw_decode("InitData", index, protocol, &mut buffer)
+    Ok(ParsedField {
+        name: String::from("InitData"),
+        value: decode_file(&mut decoder, index, INIT_DATA)?,
+    })
 }
 
-fn decode_game_metadata_json(archive: &mut Archive) -> Value {
-    let game_metadata_file = archive
-        .open_file("replay.gamemetadata.json")
-        .expect("Failed to open replay.gamemetadata.json file");
-    let mut game_metadata: Vec<u8> = vec![0; game_metadata_file.size() as usize];
-    game_metadata_file
-        .read(archive, &mut game_metadata)
-        .unwrap();
+pub fn decode_game_metadata_json(
+    archive: &mut Archive,
+) -> Result<Option<Value>, serde_json::Error> {
+    read_archive_file(archive, "replay.gamemetadata.json")
+        .map(|game_metadata| serde_json::from_slice(&game_metadata))
+        .transpose()
+}
+
+pub fn decode_game_events_data(
+    archive: &mut Archive,
+    protocol: &Protocol,
+) -> Result<Vec<Event>, ReplayError> {
+    decode_events_data_for_variant(
+        archive,
+        protocol,
+        EventTypeVariant::GameEvent,
+        &EventFilter::default(),
+    )
+}
 
-    println!("Game metadata: {:?}", game_metadata);
-    serde_json::from_slice(&game_metadata).expect("Failed to parse JSON")
+pub fn decode_message_events_data(
+    archive: &mut Archive,
+    protocol: &Protocol,
+) -> Result<Vec<Event>, ReplayError> {
+    decode_events_data_for_variant(
+        archive,
+        protocol,
+        EventTypeVariant::MessageEvent,
+        &EventFilter::default(),
+    )
 }
 
-fn decode_game_events_data(archive: &mut Archive, protocol: &Protocol) {
-    decode_events_data_for_variant(archive, protocol, EventTypeVariant::GameEvent);
+pub fn decode_tracker_events_data(
+    archive: &mut Archive,
+    protocol: &Protocol,
+) -> Result<Vec<Event>, ReplayError> {
+    decode_events_data_for_variant(
+        archive,
+        protocol,
+        EventTypeVariant::TrackerEvent,
+        &EventFilter::default(),
+    )
 }
 
-fn decode_message_events_data(archive: &mut Archive, protocol: &Protocol) {
-    decode_events_data_for_variant(archive, protocol, EventTypeVariant::MessageEvent);
+/// The protocol does not describe the sync stream, so only its raw bytes are returned.
+pub fn decode_sync_events_data(archive: &mut Archive) -> Option<Vec<u8>> {
+    read_archive_file(archive, EventTypeVariant::SyncEvent.file_name())
 }
 
-fn decode_tracker_events_data(archive: &mut Archive, protocol: &Protocol) {
-    decode_events_data_for_variant(archive, protocol, EventTypeVariant::TrackerEvent);
+/// The framing of the smartcam stream is not known yet, so only its raw bytes are returned.
+pub fn decode_smartcam_events_data(archive: &mut Archive) -> Option<Vec<u8>> {
+    read_archive_file(archive, EventTypeVariant::SmartcamEvent.file_name())
 }
 
-fn decode_events_data_for_variant(
+/// Decodes the events of a stream selected by `filter`.
+///
+/// The sync and smartcam streams have no known framing and fail with
+/// `ReplayError::UndecodableStream`, their raw bytes being available through
+/// `decode_sync_events_data` and `decode_smartcam_events_data`.
+pub fn decode_events_data_for_variant(
     archive: &mut Archive,
     protocol: &Protocol,
     event_type_variant: EventTypeVariant,
-) {
-    let (events_data_file_name, event_id_type_index, event_types, user_id_present, decode): (
-        &str,
-        usize,
-        &HashMap<u16, EventType>,
-        bool,
-        fn(
-            name: &str,
-            type_index: usize,
-            protocol: &Protocol,
-            buffer: &mut BitPackedBuff,
-        ) -> ParsedField,
-    ) = match event_type_variant {
+    filter: &EventFilter,
+) -> Result<Vec<Event>, ReplayError> {
+    let file_name = event_type_variant.file_name();
+    let events_data =
+        read_archive_file(archive, file_name).ok_or(ReplayError::MissingFile(file_name))?;
+    let (event_id_type_index, event_types, user_id_present) = match event_type_variant {
         EventTypeVariant::GameEvent => (
-            "replay.game.events",
             protocol.game_eventid_type_index.unwrap(),
             &protocol.game_event_types,
             true,
-            raw_decode,
         ),
         EventTypeVariant::MessageEvent => (
-            "replay.message.events",
             protocol.message_eventid_type_index.unwrap(),
             &protocol.message_event_types,
             true,
-            raw_decode,
         ),
         EventTypeVariant::TrackerEvent => (
-            "replay.tracker.events",
             protocol.tracker_eventid_type_index.unwrap(),
             &protocol.tracker_event_types,
             false,
-            versioned_decode,
+        ),
+        EventTypeVariant::SyncEvent | EventTypeVariant::SmartcamEvent => {
+            return Err(ReplayError::UndecodableStream(event_type_variant));
+        }
+    };
+
+    let events = match event_type_variant {
+        EventTypeVariant::TrackerEvent => decode_event_stream(
+            &mut VersionedDecoder::new(&events_data, protocol),
+            protocol,
+            event_id_type_index,
+            event_types,
+            user_id_present,
+            filter,
+        ),
+        _ => decode_event_stream(
+            &mut BitPackedDecoder::new(&events_data, protocol),
+            protocol,
+            event_id_type_index,
+            event_types,
+            user_id_present,
+            filter,
         ),
     };
-    let events_data_file = archive
-        .open_file(events_data_file_name)
-        .expect("Failed to open events file");
-    let mut events_data: Vec<u8> = vec![0; events_data_file.size() as usize];
-    events_data_file.read(archive, &mut events_data).unwrap();
+    events.map_err(|error| ReplayError::Decode {
+        file: file_name,
+        error,
+    })
+}
 
-    let mut buffer = BitPackedBuff::new_big_endian(&events_data);
+/// Decodes events prefixed with a game loop delta and, for game and message events, a user ID.
+pub fn decode_event_stream<D: Decoder>(
+    decoder: &mut D,
+    protocol: &Protocol,
+    event_id_type_index: usize,
+    event_types: &HashMap<u16, EventType>,
+    user_id_present: bool,
+    filter: &EventFilter,
+) -> Result<Vec<Event>, DecodeError> {
     let game_loop_type_index = protocol.game_loop_type_index.unwrap();
     let user_id_type_index = protocol.replay_userid_type_index.unwrap();
-    let mut loop_id: usize = 0;
-    while !buffer.done() {
-        let loop_data = match decode("loopData", game_loop_type_index, protocol, &mut buffer) {
-            ParsedField {
-                name: _,
-                value: Some(ParsedFieldType::Int(loop_data)),
-            } => loop_data as usize,
-            _ => panic!("Failed to parse game loop data in game events"),
-        };
-        println!("Loop Delta: {}", loop_data);
-        loop_id += loop_data;
-
-        let user_id;
-        if user_id_present {
-            let user_data_fields = match decode("userId", user_id_type_index, protocol, &mut buffer)
-            {
-                ParsedField {
-                    name: _,
-                    value: Some(ParsedFieldType::Struct(fields)),
-                } => fields,
-                _ => panic!("Failed to parse user ID in game events"),
-            };
-            user_id = match user_data_fields.iter().find(|f| f.name == "m_userId") {
-                Some(ParsedField {
-                    name: _,
-                    value: Some(ParsedFieldType::Int(user_id)),
-                }) => *user_id,
-                _ => panic!("Failed to find user ID in user data"),
-            };
-            println!("User ID: {}", user_id);
+    let mut events = Vec::new();
+    let mut game_loop: usize = 0;
+    while !decoder.done() {
+        let start_bits = decoder.used_bits();
+        let loop_delta = decoder
+            .try_instance(game_loop_type_index)?
+            .as_ref()
+            .and_then(ParsedFieldType::as_choice)
+            .and_then(|(_, loop_delta)| loop_delta.as_int())
+            .ok_or(DecodeError::MissingField("gameLoopDelta"))?;
+        game_loop += loop_delta as usize;
+
+        let user_id = if user_id_present {
+            let user_id = decoder
+                .try_instance(user_id_type_index)?
+                .as_ref()
+                .and_then(|user| user.field("m_userId"))
+                .and_then(ParsedFieldType::as_int)
+                .ok_or(DecodeError::MissingField("m_userId"))?;
+            Some(user_id)
         } else {
-            // user_id = -1;
-            println!("User ID is absent in Tracker Event");
+            None
+        };
+
+        let event_id = decoder
+            .try_instance(event_id_type_index)?
+            .as_ref()
+            .and_then(ParsedFieldType::as_int)
+            .ok_or(DecodeError::MissingField("eventId"))? as u16;
+        let event_type = event_types
+            .get(&event_id)
+            .ok_or(DecodeError::UnknownEventId(event_id))?;
+        if filter.is_past(game_loop) {
+            break;
+        }
+        if !filter.matches(&event_type.event_name, event_id, user_id, game_loop) {
+            decoder.skip(event_type.type_index);
+            if let Some(error) = decoder.error() {
+                return Err(error);
+            }
+            decoder.byte_align();
+            continue;
         }
 
-        let field = match decode("eventId", event_id_type_index, protocol, &mut buffer) {
-            ParsedField {
-                name: _,
-                value: Some(field),
-            } => field,
-            _ => panic!("Failed to parse event ID in game events"),
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!(
+            "event",
+            name = %event_type.event_name,
+            game_loop,
+            user_id
+        )
+        .entered();
+        let data = ParsedField {
+            name: String::from("eventData"),
+            value: decoder.try_instance(event_type.type_index)?,
         };
-        println!("Event ID: {:?}", field);
-        let event_id = match field {
-            ParsedFieldType::Int(id) => id as u16,
-            _ => panic!("Event ID is not an integer"),
+        decoder.byte_align();
+        if !filter.matches_player(&data) {
+            continue;
+        }
+
+        events.push(Event {
+            name: event_type.event_name.clone(),
+            event_id,
+            game_loop,
+            user_id,
+            bits: decoder.used_bits() - start_bits,
+            data,
+        });
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_protocol_version;
+    use std::collections::HashSet;
+
+    #[test]
+    fn it_decodes_user_data_with_no_error() {
+        let protocol = load_protocol_version("93272");
+        let mut replay = build_replay("assets/replays/test.SC2Replay", &protocol);
+        let user_data = replay.user_data();
+        assert_eq!(
+            user_data.signature,
+            Some(b"StarCraft II replay\x1b11".to_vec())
+        );
+        assert_eq!(
+            user_data.version,
+            Version {
+                flags: 1,
+                major: 5,
+                minor: 0,
+                revision: 14,
+                build: 93272,
+                base_build: 93272,
+            }
+        );
+        assert_eq!(user_data.elapsed_game_loops, 11749);
+        assert!(user_data.use_scaled_time);
+        assert_eq!(user_data.ngdp_root_key.len(), 16);
+        assert_eq!(user_data.data_build_num, 93272);
+        assert_eq!(user_data.duration().as_secs(), 524);
+    }
+
+    #[test]
+    fn it_lists_and_reads_archive_files_with_no_error() {
+        let protocol = load_protocol_version("93272");
+        let mut replay = build_replay("assets/replays/test.SC2Replay", &protocol);
+        let files = replay.files().unwrap();
+        let details = files
+            .iter()
+            .find(|file| file.name == "replay.details")
+            .expect("Failed to find replay.details");
+        assert!(files
+            .iter()
+            .any(|file| file.name == "replay.server.battlelobby"));
+
+        let raw = replay.read_raw("replay.details").unwrap();
+        assert_eq!(raw.len(), details.size as usize);
+        assert_eq!(replay.read_raw("replay.missing"), None);
+    }
+
+    #[test]
+    fn it_reads_sync_and_smartcam_streams_with_no_error() {
+        let protocol = load_protocol_version("93272");
+        let mut replay = build_replay("assets/replays/test.SC2Replay", &protocol);
+        let sync = replay.sync_events().unwrap();
+        assert_eq!(sync.len(), 915);
+
+        let smartcam = replay.smartcam_events().unwrap();
+        assert_eq!(smartcam.len(), 1533);
+
+        for variant in [EventTypeVariant::SyncEvent, EventTypeVariant::SmartcamEvent] {
+            let events = replay.filtered_events(variant, &EventFilter::default());
+            assert!(matches!(events, Err(ReplayError::UndecodableStream(_))));
+        }
+    }
+
+    #[test]
+    fn it_joins_tracker_and_game_events_on_unit_tags() {
+        let protocol = load_protocol_version("93272");
+        let mut replay = build_replay("assets/replays/test.SC2Replay", &protocol);
+        let created: HashSet<UnitTag> = replay
+            .tracker_events()
+            .iter()
+            .filter(|event| {
+                event.name == "NNet.Replay.Tracker.SUnitBornEvent"
+                    || event.name == "NNet.Replay.Tracker.SUnitInitEvent"
+            })
+            .filter_map(Event::unit_tag)
+            .collect();
+        let selected: Vec<UnitTag> = replay
+            .game_events()
+            .iter()
+            .filter(|event| event.name == "NNet.Game.SSelectionDeltaEvent")
+            .filter_map(|event| {
+                event
+                    .data
+                    .field("m_delta")?
+                    .field("m_addUnitTags")?
+                    .as_array()
+            })
+            .flatten()
+            .filter_map(|tag| Some(UnitTag::from(tag.as_int()? as u32)))
+            .collect();
+
+        assert!(!selected.is_empty());
+        assert!(selected.iter().all(|tag| created.contains(tag)));
+    }
+
+    #[test]
+    fn it_decodes_tracker_events_with_no_error() {
+        let protocol = load_protocol_version("93272");
+        let mut replay = build_replay("assets/replays/test.SC2Replay", &protocol);
+        let events = decode_tracker_events_data(&mut replay.archive, &protocol).unwrap();
+        assert_eq!(events.len(), 603);
+        assert_eq!(events[0].name, "NNet.Replay.Tracker.SPlayerSetupEvent");
+        assert_eq!(events[0].user_id, None);
+        let last = events.last().unwrap();
+        assert_eq!(last.name, "NNet.Replay.Tracker.SUnitDiedEvent");
+        assert_eq!(last.game_loop, 11515);
+        assert_eq!(last.bits, 264);
+    }
+
+    #[test]
+    fn it_skips_filtered_out_events_with_no_error() {
+        let protocol = load_protocol_version("93272");
+        let mut replay = build_replay("assets/replays/test.SC2Replay", &protocol);
+        let died = "NNet.Replay.Tracker.SUnitDiedEvent";
+        let filter = EventFilter {
+            names: vec![died.to_string()],
+            ..Default::default()
         };
-        let event_type = event_types
-            .get(&event_id)
-            .expect("Failed to get event type from protocol");
-        let event = decode("eventData", event_type.type_index, protocol, &mut buffer);
-        println!("Game Loop ID: {}", loop_id);
-        println!("Event Data: {:?}", event);
-        buffer.byte_align();
+        let events = replay
+            .filtered_events(EventTypeVariant::TrackerEvent, &filter)
+            .unwrap();
+        let expected: Vec<Event> = replay
+            .tracker_events()
+            .into_iter()
+            .filter(|event| event.name == died)
+            .collect();
+        assert!(!events.is_empty());
+        assert_eq!(events, expected);
+
+        let filter = EventFilter {
+            user_ids: vec![6],
+            loop_range: Some(1000..2000),
+            ..Default::default()
+        };
+        let events = replay
+            .filtered_events(EventTypeVariant::GameEvent, &filter)
+            .unwrap();
+        let expected: Vec<Event> = replay
+            .game_events()
+            .into_iter()
+            .filter(|event| event.user_id == Some(6) && (1000..2000).contains(&event.game_loop))
+            .collect();
+        assert!(!events.is_empty());
+        assert_eq!(events, expected);
+
+        let filter = EventFilter {
+            player_ids: vec![2],
+            ..Default::default()
+        };
+        let events = replay
+            .filtered_events(EventTypeVariant::TrackerEvent, &filter)
+            .unwrap();
+        let expected: Vec<Event> = replay
+            .tracker_events()
+            .into_iter()
+            .filter(|event| event.player_id() == Some(2))
+            .collect();
+        assert!(events
+            .iter()
+            .any(|event| event.name == "NNet.Replay.Tracker.SPlayerStatsEvent"));
+        assert!(events
+            .iter()
+            .any(|event| event.name == "NNet.Replay.Tracker.SUnitBornEvent"));
+        assert_eq!(events, expected);
     }
 }
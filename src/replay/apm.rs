@@ -0,0 +1,239 @@
+use super::camera::HOTKEY_RECALL_REASON;
+use super::game_time::{GameClock, GameTime};
+use super::types::*;
+
+/// Length of the windows of `Replay::apm`, in game seconds.
+pub const DEFAULT_WINDOW_SECONDS: f64 = 60.0;
+
+/// Actions and events per minute of a user over a window of the game.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ApmWindow {
+    pub start: GameTime,
+    pub apm: f64,
+    pub epm: f64,
+}
+
+/// Actions and events per minute of a user, in game minutes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerApm {
+    pub user_id: u8,
+    pub actions: usize,
+    pub events: usize,
+    pub apm: f64,
+    pub epm: f64,
+    pub windows: Vec<ApmWindow>,
+}
+
+/// Whether a game event is an action of its user: commands, selections, control groups and
+/// camera location hotkeys, saved or recalled.
+///
+/// Other camera moves, e.g. scrolling or clicking the minimap, have undocumented reasons and
+/// are not counted, which puts the APM a few actions per minute below the one of
+/// `replay.gamemetadata.json` for players moving the camera with the minimap.
+pub fn is_action(event: &Event) -> bool {
+    match event.name.as_str() {
+        "NNet.Game.SCmdEvent"
+        | "NNet.Game.SCmdUpdateTargetPointEvent"
+        | "NNet.Game.SCmdUpdateTargetUnitEvent"
+        | "NNet.Game.SSelectionDeltaEvent"
+        | "NNet.Game.SControlGroupUpdateEvent"
+        | "NNet.Game.SCameraSaveEvent" => true,
+        "NNet.Game.SCameraUpdateEvent" => event
+            .data
+            .field("m_reason")
+            .and_then(ParsedFieldType::as_int)
+            .is_some_and(|reason| reason == HOTKEY_RECALL_REASON),
+        _ => false,
+    }
+}
+
+/// Whether a game event was sent by the user rather than by their client on its own.
+pub fn is_user_event(event: &Event) -> bool {
+    !event.name.ends_with("SyncEvent")
+        && event.name != "NNet.Game.SUserOptionsEvent"
+        && event.name != "NNet.Game.SGameUserLeaveEvent"
+}
+
+/// Loop at which the first of `user_ids` left, which ends the game for the APM of the
+/// metadata even though the others may stay a little longer.
+pub fn game_end_loop(events: &[Event], user_ids: &[u8]) -> Option<usize> {
+    events
+        .iter()
+        .filter(|event| event.name == "NNet.Game.SGameUserLeaveEvent")
+        .find(|event| {
+            event
+                .user_id
+                .is_some_and(|id| u8::try_from(id).is_ok_and(|id| user_ids.contains(&id)))
+        })
+        .map(|event| event.game_loop)
+}
+
+/// APM and EPM of `user_id` over the game events up to `end_loop`, with windows of
+/// `window_seconds` game seconds.
+pub fn player_apm(
+    events: &[Event],
+    user_id: u8,
+    end_loop: usize,
+    clock: &GameClock,
+    window_seconds: f64,
+) -> PlayerApm {
+    let loops_per_second = clock.loops_per_game_second();
+    let window_loops = ((window_seconds * loops_per_second) as usize).max(1);
+    let window_count = end_loop.div_ceil(window_loops);
+    let mut counts = vec![(0, 0); window_count];
+
+    let user_events = events.iter().filter(|event| {
+        event.user_id == Some(user_id as isize)
+            && event.game_loop < end_loop
+            && is_user_event(event)
+    });
+    for event in user_events {
+        let count = &mut counts[event.game_loop / window_loops];
+        count.1 += 1;
+        if is_action(event) {
+            count.0 += 1;
+        }
+    }
+
+    let per_minute = |count: usize, loops: usize| match loops {
+        0 => 0.0,
+        loops => count as f64 * 60.0 * loops_per_second / loops as f64,
+    };
+    let windows = counts
+        .iter()
+        .enumerate()
+        .map(|(index, &(actions, events))| {
+            let start = index * window_loops;
+            let loops = window_loops.min(end_loop - start);
+            ApmWindow {
+                start: clock.time(start),
+                apm: per_minute(actions, loops),
+                epm: per_minute(events, loops),
+            }
+        })
+        .collect();
+    let actions = counts.iter().map(|count| count.0).sum();
+    let events = counts.iter().map(|count| count.1).sum();
+
+    PlayerApm {
+        user_id,
+        actions,
+        events,
+        apm: per_minute(actions, end_loop),
+        epm: per_minute(events, end_loop),
+        windows,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_protocol_version;
+    use crate::replay::build_replay;
+    use crate::replay::game_time::GameSpeed;
+    use crate::replay::types::fixtures::event;
+    use std::collections::HashMap;
+
+    fn camera_update(game_loop: usize, reason: isize) -> Event {
+        event(
+            "NNet.Game.SCameraUpdateEvent",
+            game_loop,
+            Some(3),
+            vec![("m_reason", ParsedFieldType::Int(reason))],
+        )
+    }
+
+    #[test]
+    fn it_counts_camera_hotkeys_as_actions() {
+        let events = vec![
+            event("NNet.Game.SUserOptionsEvent", 0, Some(3), vec![]),
+            event("NNet.Game.SCmdEvent", 100, Some(3), vec![]),
+            event(
+                "NNet.Game.SCameraSaveEvent",
+                200,
+                Some(3),
+                vec![("m_which", ParsedFieldType::Int(1))],
+            ),
+            camera_update(300, HOTKEY_RECALL_REASON),
+            camera_update(400, 4),
+            event("NNet.Game.SCameraUpdateEvent", 500, Some(3), vec![]),
+            event("NNet.Game.STriggerPingEvent", 1000, Some(3), vec![]),
+            event("NNet.Game.SCmdEvent", 2000, Some(3), vec![]),
+        ];
+        let clock = GameClock::new(GameSpeed::Normal, false);
+        let apm = player_apm(&events, 3, 1920, &clock, 60.0);
+        assert_eq!((apm.actions, apm.events), (3, 6));
+        assert_eq!((apm.apm, apm.epm), (1.5, 3.0));
+        assert_eq!(
+            apm.windows
+                .iter()
+                .map(|window| (window.apm, window.epm))
+                .collect::<Vec<_>>(),
+            vec![(3.0, 5.0), (0.0, 1.0)]
+        );
+    }
+
+    #[test]
+    fn it_ends_the_game_when_the_first_player_leaves() {
+        const LEAVE: &str = "NNet.Game.SGameUserLeaveEvent";
+        let events = vec![
+            event(LEAVE, 100, Some(259), vec![]),
+            event(LEAVE, 200, Some(3), vec![]),
+        ];
+        assert_eq!(game_end_loop(&events, &[3]), Some(200));
+        assert_eq!(game_end_loop(&events, &[6]), None);
+    }
+
+    #[test]
+    fn it_recalls_saved_camera_locations_with_hotkey_reason() {
+        let protocol = load_protocol_version("93272");
+        let mut replay = build_replay("assets/replays/test.SC2Replay", &protocol);
+        let target = |event: &Event| {
+            let target = event.data.field("m_target")?;
+            Some((target.field("x")?.as_int()?, target.field("y")?.as_int()?))
+        };
+        let mut saved = HashMap::new();
+        let mut recalls = 0;
+        for event in replay.game_events() {
+            let user_id = event.user_id;
+            if event.name == "NNet.Game.SCameraSaveEvent" {
+                saved
+                    .entry(user_id)
+                    .or_insert_with(Vec::new)
+                    .push(target(&event));
+            } else if event.name == "NNet.Game.SCameraUpdateEvent" && is_action(&event) {
+                recalls += 1;
+                assert!(saved[&user_id].contains(&target(&event)));
+            }
+        }
+        assert_eq!(recalls, 325);
+    }
+
+    #[test]
+    fn it_computes_apm_close_to_game_metadata() {
+        let protocol = load_protocol_version("93272");
+        let mut replay = build_replay("assets/replays/test.SC2Replay", &protocol);
+        let metadata = replay.game_metadata().unwrap().unwrap();
+        let apm = replay.apm();
+        assert_eq!(
+            apm.iter()
+                .map(|player| (player.user_id, player.actions, player.apm.round()))
+                .collect::<Vec<_>>(),
+            vec![(3, 1624, 305.0), (6, 1891, 355.0)]
+        );
+
+        for (player, expected) in apm.iter().zip(&metadata.players) {
+            // The metadata also counts some minimap moves of the camera.
+            assert!(player.apm <= expected.apm && player.apm >= expected.apm * 0.95);
+            assert!(player.epm > player.apm);
+        }
+
+        let windows = &apm[1].windows;
+        assert_eq!(windows.len(), 6);
+        assert_eq!(
+            (windows[1].start.to_string(), windows[5].start.to_string()),
+            ("01:00".to_string(), "05:00".to_string())
+        );
+        assert!(windows.iter().all(|window| window.apm <= window.epm));
+    }
+}
@@ -0,0 +1,176 @@
+use super::buffer::BitPackedBuff;
+use super::types::*;
+use std::str;
+
+/// Bytes preceding the realm and name of each player profile record.
+const PROFILE_MARKER: [u8; 5] = [b'L', b'2', 0, 0, 0];
+const CACHE_HANDLE_SIZE: usize = 40;
+
+/// Best-effort content of `replay.server.battlelobby`.
+///
+/// The layout of this file is not described by the protocol, so it is scanned for the
+/// records we recognise rather than decoded from a type info. Records which do not match the
+/// expected shape are ignored. Player portraits are not located yet, and the profiles are not
+/// tied to the lobby slots: match them to a user by the name before the `#`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BattleLobby {
+    /// Map and mod dependencies, in the same order as `Details::cache_handles`.
+    pub dependencies: Vec<CacheHandle>,
+    pub profiles: Vec<LobbyProfile>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LobbyProfile {
+    /// Character name with its character code, e.g. `gumiho#719`.
+    pub name: String,
+    pub region: u8,
+    pub realm: u8,
+    pub clan_tag: Option<String>,
+    pub clan_logo: Option<CacheHandle>,
+}
+
+impl BattleLobby {
+    pub fn from_bytes(data: &[u8]) -> Self {
+        let markers: Vec<usize> = data
+            .windows(PROFILE_MARKER.len())
+            .enumerate()
+            .filter(|(_, window)| *window == PROFILE_MARKER)
+            .map(|(index, _)| index)
+            .collect();
+        let profiles = markers
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &start)| {
+                let end = markers.get(i + 1).copied().unwrap_or(data.len());
+                decode_profile(data, start, end)
+            })
+            .collect();
+
+        BattleLobby {
+            dependencies: decode_dependencies(data),
+            profiles,
+        }
+    }
+}
+
+fn looks_like_cache_handle(bytes: &[u8]) -> bool {
+    bytes.len() >= CACHE_HANDLE_SIZE
+        && bytes[0..4].iter().all(u8::is_ascii_alphanumeric)
+        && bytes[4..8]
+            .iter()
+            .all(|b| *b == 0 || b.is_ascii_uppercase())
+        && bytes[4..8].iter().any(u8::is_ascii_uppercase)
+}
+
+/// Reads the run of cache handles following the local cache paths of the dependencies at the
+/// start of the file. Both lists are prefixed with the number of dependencies.
+fn decode_dependencies(data: &[u8]) -> Vec<CacheHandle> {
+    let Some(&dependency_count) = data.first() else {
+        return Vec::new();
+    };
+    let count = dependency_count as usize;
+    let start = (1..data.len()).find(|&index| {
+        data[index] == dependency_count
+            && count > 0
+            && index + 1 + count * CACHE_HANDLE_SIZE <= data.len()
+            && data[index + 1..]
+                .chunks(CACHE_HANDLE_SIZE)
+                .take(count)
+                .all(looks_like_cache_handle)
+    });
+    let Some(start) = start else {
+        return Vec::new();
+    };
+
+    let mut buffer = BitPackedBuff::new_big_endian(data);
+    buffer.byte_index = start;
+    let count = buffer.read_bits(8) as usize;
+    (0..count)
+        .filter_map(|_| CacheHandle::from_bytes(buffer.read_aligned_slice(CACHE_HANDLE_SIZE)))
+        .collect()
+}
+
+/// Decodes the profile record between the marker at `start` and the next one at `end`.
+fn decode_profile(data: &[u8], start: usize, end: usize) -> Option<LobbyProfile> {
+    let region = *data.get(start.checked_sub(3)?)?;
+    let mut buffer = BitPackedBuff::new_big_endian(data);
+    buffer.byte_index = start + PROFILE_MARKER.len();
+    if buffer.byte_index + 2 > end {
+        return None;
+    }
+    let realm = buffer.read_bits(8) as u8;
+    // Names are blobs with a minimum length of 2, stored as an offset from it.
+    let length = buffer.read_bits(8) as usize + 2;
+    if buffer.byte_index + length > end {
+        return None;
+    }
+    let name = str::from_utf8(buffer.read_aligned_slice(length)).ok()?;
+
+    let (clan_tag, clan_logo) = match decode_clan(&data[buffer.byte_index..end]) {
+        Some((tag, logo)) => (Some(tag), Some(logo)),
+        None => (None, None),
+    };
+
+    Some(LobbyProfile {
+        name: name.to_string(),
+        region,
+        realm,
+        clan_tag,
+        clan_logo,
+    })
+}
+
+/// Finds the clan logo of a profile record and the clan tag stored right before it.
+///
+/// The tag is prefixed with a byte holding its length above a presence bit, and is followed by
+/// a presence byte for the logo.
+fn decode_clan(record: &[u8]) -> Option<(String, CacheHandle)> {
+    let logo = record
+        .windows(4)
+        .position(|window| window == b"clfl")
+        .filter(|&index| index >= 2 && record[index - 1] == 1)?;
+    let handle = CacheHandle::from_bytes(record.get(logo..logo + CACHE_HANDLE_SIZE)?)?;
+
+    let tag_end = logo - 1;
+    let tag = (1..tag_end).find_map(|length| {
+        let prefix = record[tag_end - length - 1] as usize;
+        (prefix == (length << 1) | 1)
+            .then(|| str::from_utf8(&record[tag_end - length..tag_end]).ok())
+            .flatten()
+    })?;
+
+    Some((tag.to_string(), handle))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::load_protocol_version;
+    use crate::replay::build_replay;
+
+    #[test]
+    fn it_decodes_battle_lobby_with_no_error() {
+        let protocol = load_protocol_version("93272");
+        let mut replay = build_replay("assets/replays/test.SC2Replay", &protocol);
+        let details = replay.details();
+        let lobby = replay.battle_lobby().expect("Failed to find battle lobby");
+
+        assert_eq!(lobby.dependencies.len(), 9);
+        assert_eq!(lobby.dependencies, details.cache_handles);
+        assert_eq!(lobby.profiles.len(), 8);
+
+        let gumiho = &lobby.profiles[3];
+        assert_eq!(gumiho.name, "gumiho#719");
+        assert_eq!((gumiho.region, gumiho.realm), (2, 1));
+        assert_eq!(gumiho.clan_tag, None);
+
+        let glide = &lobby.profiles[1];
+        assert_eq!(glide.name, "GLIDE#279");
+        assert_eq!(glide.realm, 2);
+        assert_eq!(glide.clan_tag.as_deref(), Some("3Dǃ"));
+        let logo = glide.clan_logo.as_ref().unwrap();
+        assert_eq!(
+            (logo.extension.as_str(), logo.region.as_str()),
+            ("clfl", "EU")
+        );
+    }
+}
@@ -1,5 +1,9 @@
 use std::cmp::Ordering;
 
+/// Reader of bits and bytes over `data`.
+///
+/// Reading past the end of the data does not panic: missing bytes read as zeros and the buffer
+/// is marked as overrun, which decoders report as an error.
 #[derive(Debug, Clone, Copy)]
 pub struct BitPackedBuff<'a> {
     pub data: &'a [u8],
@@ -7,6 +11,7 @@ pub struct BitPackedBuff<'a> {
     cache: u8,
     big_endian: bool,
     bits_in_cache: usize,
+    overrun: bool,
 }
 
 impl<'a> BitPackedBuff<'a> {
@@ -19,6 +24,7 @@ impl<'a> BitPackedBuff<'a> {
             cache: 0,
             byte_index: 0,
             bits_in_cache: 0,
+            overrun: false,
         }
     }
 
@@ -29,35 +35,44 @@ impl<'a> BitPackedBuff<'a> {
     pub fn new_big_endian(data: &'a [u8]) -> Self {
         Self::new(data, true)
     }
-    pub fn display(&self) {
-        println!("Buff value: {:?}", self.data);
-    }
 
     pub fn read_bits(&mut self, n: usize) -> isize {
-        if self.big_endian {
+        if n == 0 {
+            0
+        } else if self.big_endian {
             self.read_bits_big(n)
         } else {
             self.read_bits_little(n)
         }
     }
     pub fn read_bit_array(&mut self, bits: usize) -> Vec<u8> {
+        if bits / 8 > self.remaining_bytes() {
+            self.overrun = true;
+            return Vec::new();
+        }
         let mut res = self.read_unaligned_bytes(bits / 8);
-        if bits % 8 != 0 {
+        if !bits.is_multiple_of(8) {
             res.push(self.read_bits(bits % 8) as u8);
         }
         res
     }
-    pub fn expect_and_skip_byte(&mut self, expected: u8) {
-        if self.data[self.byte_index] != expected {
-            panic!(
-                "Expected byte: {}, but found: {}",
-                expected, self.data[self.byte_index]
-            );
-        } else {
-            self.byte_index += 1;
+    /// Skips the next byte if it is `expected`, returning the byte found otherwise, `None` at the
+    /// end of the data.
+    pub fn expect_and_skip_byte(&mut self, expected: u8) -> Result<(), Option<u8>> {
+        match self.peek_byte() {
+            Some(found) if found == expected => {
+                self.byte_index += 1;
+                Ok(())
+            }
+            found => Err(found),
         }
     }
 
+    /// Next aligned byte, without consuming it.
+    pub fn peek_byte(&self) -> Option<u8> {
+        self.data.get(self.byte_index).copied()
+    }
+
     fn read_bits_little(&mut self, mut n: usize) -> isize {
         let mut bits_in_value: usize = 0;
         let mut value: isize = 0;
@@ -118,7 +133,10 @@ impl<'a> BitPackedBuff<'a> {
 
     fn init_cache(&mut self) {
         if self.bits_in_cache == 0 {
-            self.cache = self.data[self.byte_index];
+            self.cache = self.data.get(self.byte_index).copied().unwrap_or_else(|| {
+                self.overrun = true;
+                0
+            });
             self.byte_index += 1;
             self.bits_in_cache = 8;
         }
@@ -129,13 +147,17 @@ impl<'a> BitPackedBuff<'a> {
     }
 
     pub fn read_aligned_bytes(&mut self, n: usize) -> Vec<u8> {
-        let mut vec = vec![0; n];
+        self.read_aligned_slice(n).to_vec()
+    }
+
+    /// Same as `read_aligned_bytes` but borrows the bytes from the underlying data. The slice
+    /// is cut short at the end of the data.
+    pub fn read_aligned_slice(&mut self, n: usize) -> &'a [u8] {
         self.byte_align();
-        for el in vec.iter_mut() {
-            *el = self.data[self.byte_index];
-            self.byte_index += 1;
-        }
-        vec
+        let start = self.byte_index.min(self.data.len());
+        let end = start.saturating_add(n).min(self.data.len());
+        self.skip_bytes(n);
+        &self.data[start..end]
     }
 
     pub fn read_unaligned_bytes(&mut self, n: usize) -> Vec<u8> {
@@ -147,11 +169,24 @@ impl<'a> BitPackedBuff<'a> {
     }
 
     pub fn skip_bytes(&mut self, n: usize) -> &Self {
-        self.byte_index += n;
+        if n > self.remaining_bytes() {
+            self.overrun = true;
+            self.byte_index = self.byte_index.max(self.data.len());
+        } else {
+            self.byte_index += n;
+        }
 
         self
     }
 
+    pub fn skip_bits(&mut self, mut n: usize) {
+        while n > 0 {
+            let bits = n.min(32);
+            self.read_bits(bits);
+            n -= bits;
+        }
+    }
+
     /// readVarInt reads a variable-length int value.
     /// Format: read from input by 8 bits.
     ///     * Highest bit tells if have to read more bytes,
@@ -162,7 +197,7 @@ impl<'a> BitPackedBuff<'a> {
         let mut byte: isize;
         loop {
             byte = self.read_bits(8);
-            value |= (byte & 0x7f) << shift;
+            value |= (byte & 0x7f).checked_shl(shift).unwrap_or_default();
             if (byte & 0x80) == 0 {
                 break;
             } else {
@@ -180,9 +215,22 @@ impl<'a> BitPackedBuff<'a> {
         offset + self.read_bits(length)
     }
 
+    pub fn used_bits(&self) -> usize {
+        self.byte_index * 8 - self.bits_in_cache
+    }
+
     pub fn done(&self) -> bool {
         self.bits_in_cache == 0 && self.byte_index >= self.data.len()
     }
+
+    /// Whether a read went past the end of the data.
+    pub fn overrun(&self) -> bool {
+        self.overrun
+    }
+
+    fn remaining_bytes(&self) -> usize {
+        self.data.len().saturating_sub(self.byte_index)
+    }
 }
 
 #[cfg(test)]
@@ -220,6 +268,15 @@ mod tests {
             assert_eq!(vec![5, 18, 0, 2], bytes);
         }
 
+        #[test]
+        fn it_reads_zero_bits_without_consuming_a_byte() {
+            let data: [u8; 2] = [1, 18];
+            let mut buff = BitPackedBuff::new_big_endian(&data);
+            assert_eq!(0, buff.read_bits(0));
+            assert_eq!(0, buff.byte_index);
+            assert_eq!(1, buff.read_bits(8));
+        }
+
         #[test]
         fn it_reads_int() {
             let data: [u8; 2] = [1, 18];
@@ -267,8 +324,29 @@ mod tests {
             let data: [u8; 6] = [5, 18, 0, 2, 44, 83];
             let mut buff = BitPackedBuff::new_little_endian(&data);
             assert_eq!(0, buff.byte_index);
-            buff.expect_and_skip_byte(5);
+            assert_eq!(Ok(()), buff.expect_and_skip_byte(5));
             assert_eq!(1, buff.byte_index);
+            assert_eq!(Err(Some(18)), buff.expect_and_skip_byte(5));
+            assert_eq!(1, buff.byte_index);
+        }
+
+        #[test]
+        fn it_skips_bits() {
+            let data: [u8; 6] = [5, 18, 0, 2, 44, 83];
+            let mut buff = BitPackedBuff::new_little_endian(&data);
+            buff.skip_bits(43);
+            assert_eq!(43, buff.used_bits());
+            assert_eq!(10, buff.read_bits(5));
+        }
+
+        #[test]
+        fn it_marks_reads_past_the_end_as_overrun() {
+            let data: [u8; 2] = [5, 18];
+            let mut buff = BitPackedBuff::new_little_endian(&data);
+            assert_eq!(vec![5, 18], buff.read_aligned_bytes(4));
+            assert!(buff.overrun());
+            assert_eq!(0, buff.read_bits(8));
+            assert_eq!(Err(None), buff.expect_and_skip_byte(0));
         }
 
         #[test]
@@ -0,0 +1,351 @@
+use super::commands::{AbilityTable, Command, CommandPoint, CommandTarget};
+use super::game_time::{GameClock, GameTime};
+use super::stats::stats_timeline;
+use super::types::*;
+use super::units::{Unit, UnitTracker};
+use std::f64::consts::SQRT_2;
+
+const WORKERS: [&str; 4] = ["SCV", "Probe", "Drone", "MULE"];
+/// Units created by abilities rather than produced, which are never part of a build order.
+const EFFECTS: [&str; 6] = [
+    "AdeptPhaseShift",
+    "KD8Charge",
+    "Broodling",
+    "Locust",
+    "LocustMP",
+    "Interceptor",
+];
+/// Units warped in by a warp gate, which start with a `SUnitInitEvent` like structures.
+const WARP_INS: [&str; 6] = [
+    "Zealot",
+    "Stalker",
+    "Sentry",
+    "Adept",
+    "HighTemplar",
+    "DarkTemplar",
+];
+/// Eggs and cocoons units morph through, e.g. a `Zergling` to a `Baneling` through a
+/// `BanelingCocoon`, listed as the unit they hatch into.
+const MORPHS: [&str; 7] = [
+    "Egg",
+    "BanelingCocoon",
+    "RavagerCocoon",
+    "BroodLordCocoon",
+    "OverlordCocoon",
+    "TransportOverlordCocoon",
+    "LurkerMPEgg",
+];
+/// Suffixes of the add-ons, which a structure builds in place with a command without target.
+const ADD_ONS: [&str; 2] = ["Reactor", "TechLab"];
+/// Prefixes of the upgrades granted for cosmetics rather than researched.
+const COSMETIC_UPGRADES: [&str; 3] = ["Reward", "Spray", "GameHeart"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildItemKind {
+    Structure,
+    Unit,
+    Upgrade,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildOrderItem {
+    pub game_loop: usize,
+    pub time: GameTime,
+    /// Time of the command ordering the item, when it could be found.
+    pub queued: Option<GameTime>,
+    /// Supply used at the last `SPlayerStatsEvent` before the item, which lags by up to 10
+    /// game seconds.
+    pub supply: f64,
+    pub name: String,
+    pub kind: BuildItemKind,
+}
+
+impl BuildOrderItem {
+    fn new(time: GameTime, name: &str, kind: BuildItemKind) -> Self {
+        BuildOrderItem {
+            game_loop: time.game_loop,
+            time,
+            queued: None,
+            supply: 0.0,
+            name: name.to_string(),
+            kind,
+        }
+    }
+
+    pub fn is_worker(&self) -> bool {
+        WORKERS.contains(&self.name.as_str())
+    }
+
+    pub fn is_larva(&self) -> bool {
+        self.name == "Larva"
+    }
+}
+
+/// Structures, units and upgrades of `player_id` in game loop order, from the tracker
+/// `events`, without the starting units.
+///
+/// Structures and warp-ins are listed when they are started, while other units are listed
+/// when they are born since tracker events do not record when they were queued. Units morphed
+/// from larvae or other units are listed when their egg or cocoon is started, and upgrades
+/// when they complete.
+///
+/// Items are joined to the `commands` of the users of `player_id` ordering them, to tell when
+/// they were queued: structures and warp-ins by the target of the command, and trained units
+/// by the name of the ability creating them when `abilities` names the ability links of the
+/// build. Add-ons, morphs and upgrades are not joined.
+///
+/// Supply comes from the `SPlayerStatsEvent`s, recorded every 10 game seconds, so it lags
+/// behind the item by up to that much.
+pub fn build_order(
+    events: &[Event],
+    commands: &[Command],
+    player_id: u8,
+    clock: &GameClock,
+    abilities: Option<&dyn AbilityTable>,
+) -> Vec<BuildOrderItem> {
+    let mut items = Vec::new();
+    // Trained units to join with the commands by ability, as indices of `items`.
+    let mut trained = Vec::new();
+
+    let tracker = UnitTracker::from_events(events);
+    for unit in tracker.units() {
+        let owner = unit.owners.first().map(|owner| owner.control_player_id);
+        if unit.born_loop == 0 || owner != Some(player_id) {
+            continue;
+        }
+        let name = unit.types[0].unit_type.as_str();
+        if EFFECTS.contains(&name) {
+            continue;
+        }
+        let started = unit.completed_loop != Some(unit.born_loop);
+        let kind = if started && !WARP_INS.contains(&name) {
+            BuildItemKind::Structure
+        } else {
+            BuildItemKind::Unit
+        };
+        let mut item = BuildOrderItem::new(clock.time(unit.born_loop), name, kind);
+        if started {
+            if !ADD_ONS.iter().any(|suffix| name.ends_with(suffix)) {
+                item.queued =
+                    placing_command(commands, unit).map(|command| clock.time(command.game_loop));
+            }
+        } else if let Some(ability) = unit.creator_ability.as_deref() {
+            trained.push((items.len(), ability));
+        }
+        items.push(item);
+
+        // Larvae turn into eggs and units into cocoons, which turn into the unit once it
+        // hatches, or back into the unit when the morph is cancelled.
+        for (index, pair) in unit.types.windows(2).enumerate() {
+            let reverted = index > 0 && unit.types[index - 1].unit_type == pair[1].unit_type;
+            if MORPHS.contains(&pair[0].unit_type.as_str()) && !reverted {
+                items.push(BuildOrderItem::new(
+                    clock.time(pair[0].game_loop),
+                    &pair[1].unit_type,
+                    BuildItemKind::Unit,
+                ));
+            }
+        }
+    }
+
+    if let Some(abilities) = abilities {
+        // Units are trained in the order they were queued, one command each.
+        trained.sort_by_key(|&(index, _)| items[index].game_loop);
+        let mut joined = vec![false; commands.len()];
+        for (index, ability) in trained {
+            let born_loop = items[index].game_loop;
+            let command = commands.iter().zip(&joined).position(|(command, joined)| {
+                !joined
+                    && command.game_loop <= born_loop
+                    && command.ability_name(abilities) == Some(ability)
+            });
+            if let Some(position) = command {
+                joined[position] = true;
+                items[index].queued = Some(clock.time(commands[position].game_loop));
+            }
+        }
+    }
+
+    let upgrades = events
+        .iter()
+        .filter(|event| event.name == "NNet.Replay.Tracker.SUpgradeEvent")
+        .filter_map(|event| event.data.value.as_ref().map(|data| (event, data)))
+        .filter(|(_, data)| int(data, "m_playerId") == player_id as isize);
+    for (event, data) in upgrades {
+        let name = string(data, "m_upgradeTypeName");
+        if !COSMETIC_UPGRADES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+        {
+            items.push(BuildOrderItem::new(
+                clock.time(event.game_loop),
+                &name,
+                BuildItemKind::Upgrade,
+            ));
+        }
+    }
+
+    items.sort_by_key(|item| item.game_loop);
+    let stats = stats_timeline(events, player_id);
+    for item in &mut items {
+        item.supply = stats
+            .iter()
+            .take_while(|stats| stats.game_loop <= item.game_loop)
+            .last()
+            .map(|stats| stats.supply_used())
+            .unwrap_or_default();
+    }
+    items
+}
+
+/// Last command before `unit` was started targeting where it was placed, i.e. the command
+/// building a structure or warping in a unit.
+fn placing_command<'c>(commands: &'c [Command], unit: &Unit) -> Option<&'c Command> {
+    let position = unit.positions.first()?;
+    // Tracker events round positions down to whole cells, less than a diagonal from the target.
+    let is_placed_at = |point: &CommandPoint| {
+        (point.x - position.x as f64).hypot(point.y - position.y as f64) < SQRT_2
+    };
+    commands
+        .iter()
+        .take_while(|command| command.game_loop <= unit.born_loop)
+        .filter(|command| command.ability.is_some())
+        .filter(|command| match &command.target {
+            CommandTarget::Point(point) | CommandTarget::Unit { point, .. } => is_placed_at(point),
+            _ => false,
+        })
+        .last()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_protocol_version;
+    use crate::replay::build_replay;
+    use crate::replay::commands::JsonAbilityTable;
+    use crate::replay::types::fixtures::event;
+
+    #[test]
+    fn it_extracts_build_order_with_no_error() {
+        let protocol = load_protocol_version("93272");
+        let mut replay = build_replay("assets/replays/test.SC2Replay", &protocol);
+        let build_order: Vec<BuildOrderItem> = replay
+            .build_order(2)
+            .into_iter()
+            .filter(|item| !item.is_worker() && !item.is_larva())
+            .collect();
+
+        let first = &build_order[0];
+        assert_eq!(
+            (first.game_loop, first.name.as_str(), first.kind),
+            (380, "Pylon", BuildItemKind::Structure)
+        );
+        assert_eq!(first.supply, 14.0);
+        assert_eq!(first.time.to_string(), "00:16");
+
+        let names: Vec<&str> = build_order
+            .iter()
+            .take(4)
+            .map(|item| item.name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            ["Pylon", "Gateway", "Assimilator", "CyberneticsCore"]
+        );
+        let queued = |name: &str| {
+            build_order
+                .iter()
+                .filter(|item| item.name == name)
+                .map(|item| item.queued.map(|queued| queued.game_loop))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(queued("Pylon")[..2], [Some(380), Some(2286)]);
+        assert_eq!(queued("Assimilator"), [Some(1019), Some(2453), Some(5956)]);
+        assert_eq!(queued("TwilightCouncil"), [Some(3313)]);
+        // Trained from a gateway, then warped in.
+        assert_eq!(queued("Stalker")[..2], [None, Some(5155)]);
+
+        let blink = build_order
+            .iter()
+            .find(|item| item.kind == BuildItemKind::Upgrade && item.name == "BlinkTech")
+            .unwrap();
+        assert_eq!(blink.game_loop, 6473);
+        assert!(build_order
+            .iter()
+            .all(|item| !item.name.starts_with("Spray") && item.name != "AdeptPhaseShift"));
+        assert!(build_order
+            .iter()
+            .any(|item| item.name == "Stalker" && item.kind == BuildItemKind::Unit));
+    }
+
+    #[test]
+    fn it_joins_trained_units_to_their_commands_by_ability() {
+        let protocol = load_protocol_version("93272");
+        let mut replay = build_replay("assets/replays/test.SC2Replay", &protocol);
+        let table = JsonAbilityTable::from_json(r#"{"177": ["NexusTrain"]}"#).unwrap();
+        let probes: Vec<(usize, Option<usize>)> = replay
+            .build_order_with_abilities(2, Some(&table))
+            .iter()
+            .filter(|item| item.name == "Probe")
+            .take(3)
+            .map(|item| (item.game_loop, item.queued.map(|queued| queued.game_loop)))
+            .collect();
+        assert_eq!(probes, [(279, Some(7)), (550, Some(196)), (781, Some(494))]);
+
+        let adept = replay
+            .build_order(2)
+            .into_iter()
+            .find(|item| item.name == "Adept")
+            .unwrap();
+        assert_eq!(adept.queued, None);
+    }
+
+    fn tracker_event(name: &str, game_loop: usize, index: isize, unit_type: &str) -> Event {
+        event(
+            name,
+            game_loop,
+            None,
+            vec![
+                ("m_unitTagIndex", ParsedFieldType::Int(index)),
+                ("m_unitTagRecycle", ParsedFieldType::Int(1)),
+                ("m_unitTypeName", ParsedFieldType::Blob(unit_type.into())),
+                ("m_controlPlayerId", ParsedFieldType::Int(1)),
+                ("m_upkeepPlayerId", ParsedFieldType::Int(1)),
+                ("m_x", ParsedFieldType::Int(40)),
+                ("m_y", ParsedFieldType::Int(30)),
+            ],
+        )
+    }
+
+    #[test]
+    fn it_lists_morphs_when_their_cocoon_starts() {
+        let born = "NNet.Replay.Tracker.SUnitBornEvent";
+        let type_change = "NNet.Replay.Tracker.SUnitTypeChangeEvent";
+        let events = vec![
+            tracker_event(born, 100, 1, "Zergling"),
+            tracker_event(born, 100, 2, "Zergling"),
+            tracker_event(born, 150, 3, "Overlord"),
+            tracker_event(type_change, 200, 1, "BanelingCocoon"),
+            tracker_event(type_change, 220, 2, "BanelingCocoon"),
+            tracker_event(type_change, 250, 3, "OverlordCocoon"),
+            tracker_event(type_change, 260, 2, "Zergling"),
+            tracker_event(type_change, 480, 1, "Baneling"),
+            tracker_event(type_change, 650, 3, "Overseer"),
+        ];
+        let build_order = build_order(&events, &[], 1, &GameClock::default(), None);
+        let items: Vec<(usize, &str)> = build_order
+            .iter()
+            .map(|item| (item.game_loop, item.name.as_str()))
+            .collect();
+        assert_eq!(
+            items,
+            [
+                (100, "Zergling"),
+                (100, "Zergling"),
+                (150, "Overlord"),
+                (200, "Baneling"),
+                (250, "Overseer")
+            ]
+        );
+    }
+}
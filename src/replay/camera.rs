@@ -0,0 +1,279 @@
+use super::game_time::{GameClock, Timed};
+use super::types::*;
+
+/// Fixed point scale of camera coordinates and distances.
+const CAMERA_SCALE: f64 = 256.0;
+/// Angles are stored in 1/65536 of a turn.
+const ANGLE_SCALE: f64 = 65536.0 / 360.0;
+/// Half of the size of a screen in map units. Replays do not record the resolution or zoom of
+/// the users, so a screen is taken as 24 by 14 map units, the 16:9 ratio of common displays,
+/// by convention rather than as the view of the user. Moving the camera target further than
+/// that from the target of the previous screen counts as looking at a new screen.
+pub const HALF_SCREEN_WIDTH: f64 = 12.0;
+pub const HALF_SCREEN_HEIGHT: f64 = 7.0;
+/// Distance in map units from the starting town hall within which the camera is at home, by
+/// convention roughly the extent of a main base, so that the camera is away when looking at
+/// the rest of the map.
+pub const HOME_RADIUS: f64 = 25.0;
+pub const CAMERA_HOTKEY_COUNT: usize = 8;
+/// `m_reason` of the `SCameraUpdateEvent`s recalling a camera location hotkey. s2protocol does
+/// not name the reasons, but the target of these updates is always a location saved before by
+/// a `SCameraSaveEvent` of the user.
+pub const HOTKEY_RECALL_REASON: isize = 7;
+
+/// Camera of a user after a `SCameraUpdateEvent`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraPosition {
+    pub game_loop: usize,
+    /// Target of the camera in map units, carried over from the previous update when the
+    /// event only changes the angle or zoom.
+    pub x: f64,
+    pub y: f64,
+    pub distance: Option<f64>,
+    /// Angles in degrees.
+    pub pitch: Option<f64>,
+    pub yaw: Option<f64>,
+    pub follow: bool,
+}
+
+impl Timed for CameraPosition {
+    fn game_loop(&self) -> usize {
+        self.game_loop
+    }
+}
+
+impl CameraPosition {
+    fn distance_to(&self, (x, y): (f64, f64)) -> f64 {
+        ((self.x - x).powi(2) + (self.y - y).powi(2)).sqrt()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraMetrics {
+    pub screens: usize,
+    pub screens_per_minute: f64,
+    /// Seconds spent looking within `HOME_RADIUS` of the starting town hall.
+    pub home_seconds: f64,
+    pub away_seconds: f64,
+    /// Number of `SCameraSaveEvent`s for each camera location hotkey.
+    pub hotkey_saves: [usize; CAMERA_HOTKEY_COUNT],
+    /// Number of camera updates recalling each camera location hotkey, found by their target
+    /// being the location the hotkey last saved. When several hotkeys hold that location, the
+    /// recall goes to the one which saved it last.
+    pub hotkey_recalls: [usize; CAMERA_HOTKEY_COUNT],
+}
+
+/// Camera positions of `user_id` in game loop order.
+pub fn camera_track(events: &[Event], user_id: u8) -> Vec<CameraPosition> {
+    let mut track: Vec<CameraPosition> = Vec::new();
+    let updates = events.iter().filter(|event| {
+        event.name == "NNet.Game.SCameraUpdateEvent" && event.user_id == Some(user_id as isize)
+    });
+    for event in updates {
+        let Some(data) = event.data.value.as_ref() else {
+            continue;
+        };
+        let optional = |name| {
+            data.field(name)
+                .and_then(ParsedFieldType::as_int)
+                .map(|value| value as f64)
+        };
+        let target = data
+            .field("m_target")
+            .map(|target| {
+                (
+                    int(target, "x") as f64 / CAMERA_SCALE,
+                    int(target, "y") as f64 / CAMERA_SCALE,
+                )
+            })
+            .or_else(|| track.last().map(|previous| (previous.x, previous.y)));
+        let Some((x, y)) = target else {
+            continue;
+        };
+        track.push(CameraPosition {
+            game_loop: event.game_loop,
+            x,
+            y,
+            distance: optional("m_distance").map(|distance| distance / CAMERA_SCALE),
+            pitch: optional("m_pitch").map(|pitch| pitch / ANGLE_SCALE),
+            yaw: optional("m_yaw").map(|yaw| yaw / ANGLE_SCALE),
+            follow: boolean(data, "m_follow"),
+        });
+    }
+    track
+}
+
+/// Camera metrics of `user_id` up to `end_loop`, with `home` the position of their starting
+/// town hall in map units. Everything counts as away for users without a home, like
+/// observers.
+pub fn camera_metrics(
+    events: &[Event],
+    user_id: u8,
+    home: Option<(f64, f64)>,
+    end_loop: usize,
+    clock: &GameClock,
+) -> CameraMetrics {
+    let track: Vec<CameraPosition> = camera_track(events, user_id)
+        .into_iter()
+        .filter(|position| position.game_loop < end_loop)
+        .collect();
+
+    let mut screens = 0;
+    let mut screen: Option<&CameraPosition> = None;
+    for position in &track {
+        let is_new_screen = screen.is_none_or(|screen| {
+            (position.x - screen.x).abs() > HALF_SCREEN_WIDTH
+                || (position.y - screen.y).abs() > HALF_SCREEN_HEIGHT
+        });
+        if is_new_screen {
+            screens += 1;
+            screen = Some(position);
+        }
+    }
+
+    let (mut home_loops, mut away_loops) = (0, 0);
+    for (index, position) in track.iter().enumerate() {
+        let until = track.get(index + 1).map_or(end_loop, |next| next.game_loop);
+        let loops = until - position.game_loop;
+        if home.is_some_and(|home| position.distance_to(home) <= HOME_RADIUS) {
+            home_loops += loops;
+        } else {
+            away_loops += loops;
+        }
+    }
+
+    let mut hotkey_saves = [0; CAMERA_HOTKEY_COUNT];
+    let mut hotkey_recalls = [0; CAMERA_HOTKEY_COUNT];
+    // Location last saved by each hotkey, with the number of saves before it.
+    let mut saved = [None; CAMERA_HOTKEY_COUNT];
+    let mut save_count = 0;
+    let user_events = events
+        .iter()
+        .filter(|event| event.user_id == Some(user_id as isize) && event.game_loop < end_loop);
+    for event in user_events {
+        let target = event
+            .data
+            .field("m_target")
+            .map(|target| (int(target, "x"), int(target, "y")));
+        match event.name.as_str() {
+            "NNet.Game.SCameraSaveEvent" => {
+                let which = event
+                    .data
+                    .field("m_which")
+                    .and_then(ParsedFieldType::as_int)
+                    .unwrap_or_default() as usize;
+                if which < CAMERA_HOTKEY_COUNT {
+                    hotkey_saves[which] += 1;
+                    saved[which] = target.map(|target| (target, save_count));
+                    save_count += 1;
+                }
+            }
+            "NNet.Game.SCameraUpdateEvent"
+                if event
+                    .data
+                    .field("m_reason")
+                    .and_then(ParsedFieldType::as_int)
+                    == Some(HOTKEY_RECALL_REASON) =>
+            {
+                let which = saved
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(which, save)| {
+                        save.filter(|(location, _)| Some(*location) == target)
+                            .map(|(_, order)| (order, which))
+                    })
+                    .max()
+                    .map(|(_, which)| which);
+                if let Some(which) = which {
+                    hotkey_recalls[which] += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let loops_per_second = clock.loops_per_game_second();
+    let minutes = end_loop as f64 / loops_per_second / 60.0;
+    CameraMetrics {
+        screens,
+        screens_per_minute: if minutes > 0.0 {
+            screens as f64 / minutes
+        } else {
+            0.0
+        },
+        home_seconds: home_loops as f64 / loops_per_second,
+        away_seconds: away_loops as f64 / loops_per_second,
+        hotkey_saves,
+        hotkey_recalls,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_protocol_version;
+    use crate::replay::build_replay;
+    use crate::replay::types::fixtures::{event, fields};
+
+    #[test]
+    fn it_reconstructs_camera_with_no_error() {
+        let protocol = load_protocol_version("93272");
+        let mut replay = build_replay("assets/replays/test.SC2Replay", &protocol);
+        let track = replay.camera_track(3);
+        let first = &track[0];
+        assert_eq!(first.game_loop, 3);
+        assert_eq!((first.x, first.y), (36.75, 122.0078125));
+        assert_eq!(first.distance, None);
+
+        let metrics = replay.camera_metrics(3);
+        assert_eq!(metrics.screens, 235);
+        assert_eq!(
+            (metrics.home_seconds, metrics.away_seconds),
+            (3417.0 / 22.4, 3912.0 / 22.4)
+        );
+        assert_eq!(metrics.hotkey_saves, [1, 1, 7, 2, 1, 1, 1, 1]);
+        assert_eq!(metrics.hotkey_recalls, [13, 33, 2, 32, 1, 1, 1, 1]);
+
+        let metrics = replay.camera_metrics(6);
+        assert_eq!(metrics.screens, 359);
+        assert_eq!(metrics.hotkey_saves, [0, 2, 7, 9, 16, 7, 6, 0]);
+        assert_eq!(metrics.hotkey_recalls, [0, 95, 94, 18, 24, 5, 5, 0]);
+
+        let observer = replay.camera_metrics(0);
+        assert_eq!(observer.screens, 45);
+        assert_eq!(observer.home_seconds, 0.0);
+    }
+
+    #[test]
+    fn it_recalls_the_hotkey_which_saved_the_location_last() {
+        let target = || {
+            fields(vec![
+                ("x", ParsedFieldType::Int(100)),
+                ("y", ParsedFieldType::Int(200)),
+            ])
+        };
+        let save = |game_loop, which| {
+            event(
+                "NNet.Game.SCameraSaveEvent",
+                game_loop,
+                Some(3),
+                vec![
+                    ("m_which", ParsedFieldType::Int(which)),
+                    ("m_target", target()),
+                ],
+            )
+        };
+        let recall = event(
+            "NNet.Game.SCameraUpdateEvent",
+            30,
+            Some(3),
+            vec![
+                ("m_reason", ParsedFieldType::Int(HOTKEY_RECALL_REASON)),
+                ("m_target", target()),
+            ],
+        );
+        let events = vec![save(10, 2), save(20, 0), recall];
+        let metrics = camera_metrics(&events, 3, None, 100, &GameClock::default());
+        assert_eq!(metrics.hotkey_recalls, [1, 0, 0, 0, 0, 0, 0, 0]);
+    }
+}
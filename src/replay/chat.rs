@@ -0,0 +1,202 @@
+use super::details::Details;
+use super::game_time::{GameClock, GameTime};
+use super::init_data::InitData;
+use super::types::*;
+use std::collections::HashMap;
+
+/// Fixed point scale of the coordinates of pings, the same as for command targets.
+const POINT_SCALE: f64 = 4096.0;
+
+/// Audience of a chat message or ping, from `m_recipient`.
+///
+/// The protocol only describes `m_recipient` as a 3 bits integer. The values follow sc2reader's
+/// `ChatEvent` (0 to all, 2 to allies, 4 to observers), the other values being kept as is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recipient {
+    All,
+    Allies,
+    Observers,
+    Unknown(u8),
+}
+
+impl From<isize> for Recipient {
+    fn from(value: isize) -> Self {
+        match value {
+            0 => Recipient::All,
+            2 => Recipient::Allies,
+            4 => Recipient::Observers,
+            other => Recipient::Unknown(other as u8),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatMessage {
+    pub game_loop: usize,
+    pub time: GameTime,
+    pub user_id: u8,
+    /// Name of the sender, empty when it could not be resolved.
+    pub sender: String,
+    pub recipient: Recipient,
+    pub text: String,
+}
+
+/// Minimap ping, from `SPingMessage`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ping {
+    pub game_loop: usize,
+    pub time: GameTime,
+    pub user_id: u8,
+    pub sender: String,
+    pub recipient: Recipient,
+    /// Pinged point in map cells.
+    pub x: f64,
+    pub y: f64,
+}
+
+/// Names of the users by user id.
+///
+/// Names come from the initial data of each user, falling back to the details of the player
+/// sharing its working set slot for users whose initial data has no name.
+pub fn user_names(details: &Details, init_data: &InitData) -> HashMap<u8, String> {
+    init_data
+        .lobby_state
+        .slots
+        .iter()
+        .filter_map(|slot| {
+            let user_id = slot.user_id?;
+            let name = init_data
+                .user_initial_data
+                .get(user_id as usize)
+                .map(|data| data.name.clone())
+                .filter(|name| !name.is_empty())
+                .or_else(|| {
+                    details
+                        .players
+                        .iter()
+                        .find(|player| {
+                            slot.working_set_slot_id.is_some()
+                                && player.working_set_slot_id == slot.working_set_slot_id
+                        })
+                        .map(|player| player.name.clone())
+                })?;
+            Some((user_id, name))
+        })
+        .collect()
+}
+
+/// Chat messages of the message events in game loop order.
+pub fn chat(events: &[Event], names: &HashMap<u8, String>, clock: &GameClock) -> Vec<ChatMessage> {
+    messages(events, "NNet.Game.SChatMessage")
+        .map(|(event, user_id, data)| ChatMessage {
+            game_loop: event.game_loop,
+            time: clock.time(event.game_loop),
+            user_id,
+            sender: names.get(&user_id).cloned().unwrap_or_default(),
+            recipient: Recipient::from(int(data, "m_recipient")),
+            text: string(data, "m_string"),
+        })
+        .collect()
+}
+
+/// Minimap pings of the message events in game loop order.
+pub fn pings(events: &[Event], names: &HashMap<u8, String>, clock: &GameClock) -> Vec<Ping> {
+    messages(events, "NNet.Game.SPingMessage")
+        .map(|(event, user_id, data)| {
+            let point = data.field("m_point");
+            let coordinate =
+                |name| point.map(|point| int(point, name)).unwrap_or_default() as f64 / POINT_SCALE;
+            Ping {
+                game_loop: event.game_loop,
+                time: clock.time(event.game_loop),
+                user_id,
+                sender: names.get(&user_id).cloned().unwrap_or_default(),
+                recipient: Recipient::from(int(data, "m_recipient")),
+                x: coordinate("x"),
+                y: coordinate("y"),
+            }
+        })
+        .collect()
+}
+
+fn messages<'e>(
+    events: &'e [Event],
+    name: &'e str,
+) -> impl Iterator<Item = (&'e Event, u8, &'e ParsedFieldType)> {
+    events
+        .iter()
+        .filter(move |event| event.name == name)
+        .filter_map(|event| {
+            let user_id = u8::try_from(event.user_id?).ok()?;
+            Some((event, user_id, event.data.value.as_ref()?))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_protocol_version;
+    use crate::replay::build_replay;
+    use crate::replay::types::fixtures::{event, fields};
+
+    #[test]
+    fn it_extracts_chat_with_no_error() {
+        let protocol = load_protocol_version("93272");
+        let mut replay = build_replay("assets/replays/test.SC2Replay", &protocol);
+        let chat = replay.chat();
+        assert_eq!(chat.len(), 1);
+
+        let message = &chat[0];
+        assert_eq!((message.game_loop, message.user_id), (7155, 6));
+        assert_eq!(message.recipient, Recipient::All);
+        assert_eq!(message.text, "GG WP");
+        assert_eq!(message.sender, "LiquidClem");
+        assert_eq!(message.time.to_string(), "05:19");
+
+        assert!(replay.pings().is_empty());
+    }
+
+    #[test]
+    fn it_extracts_messages_to_allies() {
+        let point = fields(vec![
+            ("x", ParsedFieldType::Int(40 * 4096)),
+            ("y", ParsedFieldType::Int(30 * 4096 + 2048)),
+        ]);
+        let message = |user_id, text: &[u8]| {
+            event(
+                "NNet.Game.SChatMessage",
+                320,
+                Some(user_id),
+                vec![
+                    ("m_recipient", ParsedFieldType::Int(2)),
+                    ("m_string", ParsedFieldType::Blob(text.to_vec())),
+                ],
+            )
+        };
+        let events = vec![
+            message(3, b"gl hf"),
+            // Not a user id: must not be read as user 3 once truncated to a byte.
+            message(259, b"lag"),
+            event(
+                "NNet.Game.SPingMessage",
+                320,
+                Some(3),
+                vec![("m_recipient", ParsedFieldType::Int(4)), ("m_point", point)],
+            ),
+        ];
+        let names = HashMap::from([(3, String::from("gumiho"))]);
+        let clock = GameClock::default();
+
+        let chat = chat(&events, &names, &clock);
+        assert_eq!(chat.len(), 1);
+        assert_eq!(chat[0].recipient, Recipient::Allies);
+        assert_eq!(chat[0].text, "gl hf");
+        assert_eq!(chat[0].sender, "gumiho");
+
+        let pings = pings(&events, &names, &clock);
+        assert_eq!(pings.len(), 1);
+        assert_eq!(pings[0].recipient, Recipient::Observers);
+        assert_eq!((pings[0].x, pings[0].y), (40.0, 30.5));
+        assert_eq!(Recipient::from(1), Recipient::Unknown(1));
+    }
+}
@@ -0,0 +1,333 @@
+use super::game_time::Timed;
+use super::types::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Fixed point scale of the coordinates of command targets.
+const POINT_SCALE: f64 = 4096.0;
+
+/// Bitfield of `m_cmdFlags`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct CommandFlags(pub u32);
+
+impl CommandFlags {
+    pub const ALTERNATE: u32 = 1 << 0;
+    pub const QUEUED: u32 = 1 << 1;
+    pub const PREEMPT: u32 = 1 << 2;
+    pub const SMART_CLICK: u32 = 1 << 3;
+    pub const SMART_RALLY: u32 = 1 << 4;
+    pub const SUBGROUP: u32 = 1 << 5;
+    pub const SET_AUTOCAST: u32 = 1 << 6;
+    pub const SET_AUTOCAST_ON: u32 = 1 << 7;
+    pub const USER: u32 = 1 << 8;
+    pub const DATA_A: u32 = 1 << 9;
+    pub const DATA_B: u32 = 1 << 10;
+    pub const AI: u32 = 1 << 11;
+    pub const AI_IGNORE_ON_FINISH: u32 = 1 << 12;
+    pub const IS_ORDER: u32 = 1 << 13;
+    pub const SCRIPT: u32 = 1 << 14;
+    pub const HOMOGENOUS_INTERRUPTION: u32 = 1 << 15;
+    pub const MINIMAP: u32 = 1 << 16;
+    pub const REPEAT: u32 = 1 << 17;
+    pub const DISPATCH_TO_OTHER_UNIT: u32 = 1 << 18;
+    pub const TARGET_SELF: u32 = 1 << 19;
+
+    const NAMES: [(u32, &'static str); 20] = [
+        (Self::ALTERNATE, "alternate"),
+        (Self::QUEUED, "queued"),
+        (Self::PREEMPT, "preempt"),
+        (Self::SMART_CLICK, "smart_click"),
+        (Self::SMART_RALLY, "smart_rally"),
+        (Self::SUBGROUP, "subgroup"),
+        (Self::SET_AUTOCAST, "set_autocast"),
+        (Self::SET_AUTOCAST_ON, "set_autocast_on"),
+        (Self::USER, "user"),
+        (Self::DATA_A, "data_a"),
+        (Self::DATA_B, "data_b"),
+        (Self::AI, "ai"),
+        (Self::AI_IGNORE_ON_FINISH, "ai_ignore_on_finish"),
+        (Self::IS_ORDER, "is_order"),
+        (Self::SCRIPT, "script"),
+        (Self::HOMOGENOUS_INTERRUPTION, "homogenous_interruption"),
+        (Self::MINIMAP, "minimap"),
+        (Self::REPEAT, "repeat"),
+        (Self::DISPATCH_TO_OTHER_UNIT, "dispatch_to_other_unit"),
+        (Self::TARGET_SELF, "target_self"),
+    ];
+
+    pub fn contains(&self, flag: u32) -> bool {
+        self.0 & flag == flag
+    }
+
+    pub fn is_queued(&self) -> bool {
+        self.contains(Self::QUEUED)
+    }
+
+    pub fn is_smart_click(&self) -> bool {
+        self.contains(Self::SMART_CLICK)
+    }
+
+    pub fn is_minimap(&self) -> bool {
+        self.contains(Self::MINIMAP)
+    }
+
+    pub fn is_autocast(&self) -> bool {
+        self.contains(Self::SET_AUTOCAST)
+    }
+
+    /// Names of the flags which are set, ignoring the bits without a known meaning.
+    pub fn names(&self) -> Vec<&'static str> {
+        Self::NAMES
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name)
+            .collect()
+    }
+}
+
+/// Point in map cells.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CommandPoint {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl CommandPoint {
+    fn from_fields(value: &ParsedFieldType) -> Self {
+        let coordinate = |name| int(value, name) as f64 / POINT_SCALE;
+        CommandPoint {
+            x: coordinate("x"),
+            y: coordinate("y"),
+            z: coordinate("z"),
+        }
+    }
+}
+
+/// Target of a command, from the `m_data` choice.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandTarget {
+    None,
+    Point(CommandPoint),
+    Unit {
+        tag: UnitTag,
+        /// Unit type link of the target when the command was issued.
+        unit_link: u16,
+        control_player_id: Option<u8>,
+        upkeep_player_id: Option<u8>,
+        point: CommandPoint,
+    },
+    Data(u32),
+}
+
+impl CommandTarget {
+    fn from_choice(value: Option<&ParsedFieldType>) -> Self {
+        let Some((variant, value)) = value.and_then(ParsedFieldType::as_choice) else {
+            return CommandTarget::None;
+        };
+        let player = |name| {
+            value
+                .field(name)
+                .and_then(|id| id.as_int())
+                .and_then(|id| u8::try_from(id).ok())
+        };
+        match variant {
+            "TargetPoint" => CommandTarget::Point(CommandPoint::from_fields(value)),
+            "TargetUnit" => CommandTarget::Unit {
+                tag: UnitTag(int(value, "m_tag") as u32),
+                unit_link: int(value, "m_snapshotUnitLink") as u16,
+                control_player_id: player("m_snapshotControlPlayerId"),
+                upkeep_player_id: player("m_snapshotUpkeepPlayerId"),
+                point: value
+                    .field("m_snapshotPoint")
+                    .map(CommandPoint::from_fields)
+                    .unwrap_or(CommandPoint {
+                        x: 0.0,
+                        y: 0.0,
+                        z: 0.0,
+                    }),
+            },
+            "Data" => CommandTarget::Data(value.as_int().unwrap_or_default() as u32),
+            _ => CommandTarget::None,
+        }
+    }
+}
+
+/// Ability ordered by a command, as links into the game data of the replay's build.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AbilityRef {
+    pub link: u16,
+    pub cmd_index: u8,
+    pub cmd_data: Option<u32>,
+}
+
+/// Typed `SCmdEvent`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Command {
+    pub game_loop: usize,
+    pub user_id: u8,
+    pub flags: CommandFlags,
+    /// `None` for smart clicks, which let the units pick their ability.
+    pub ability: Option<AbilityRef>,
+    pub target: CommandTarget,
+    pub sequence: u32,
+    pub other_unit: Option<UnitTag>,
+}
+
+impl Timed for Command {
+    fn game_loop(&self) -> usize {
+        self.game_loop
+    }
+}
+
+impl Command {
+    /// Decodes a `SCmdEvent`, `None` for any other event.
+    pub fn from_event(event: &Event) -> Option<Self> {
+        if event.name != "NNet.Game.SCmdEvent" {
+            return None;
+        }
+        let data = event.data.value.as_ref()?;
+        Some(Command {
+            game_loop: event.game_loop,
+            user_id: u8::try_from(event.user_id?).ok()?,
+            flags: CommandFlags(int(data, "m_cmdFlags") as u32),
+            ability: data.field("m_abil").map(|abil| AbilityRef {
+                link: int(abil, "m_abilLink") as u16,
+                cmd_index: int(abil, "m_abilCmdIndex") as u8,
+                cmd_data: abil
+                    .field("m_abilCmdData")
+                    .and_then(ParsedFieldType::as_int)
+                    .map(|data| data as u32),
+            }),
+            target: CommandTarget::from_choice(data.field("m_data")),
+            sequence: int(data, "m_sequence") as u32,
+            other_unit: data
+                .field("m_otherUnit")
+                .and_then(ParsedFieldType::as_int)
+                .map(|tag| UnitTag(tag as u32)),
+        })
+    }
+
+    /// Name of the ordered ability in `table`, e.g. `NexusTrain` or `Attack`.
+    pub fn ability_name<'t>(&self, table: &'t dyn AbilityTable) -> Option<&'t str> {
+        let ability = self.ability?;
+        table.ability_name(ability.link, ability.cmd_index)
+    }
+}
+
+/// Resolves ability links to names. Links are indices into the game data of a build, so a
+/// table is only valid for the builds it was extracted from.
+pub trait AbilityTable {
+    fn ability_name(&self, link: u16, cmd_index: u8) -> Option<&str>;
+}
+
+/// Ability table loaded from JSON mapping each ability link to the names of its commands,
+/// e.g. `{"177": ["NexusTrain"]}`. Commands past the end of the list have no name.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct JsonAbilityTable {
+    abilities: HashMap<u16, Vec<String>>,
+}
+
+impl JsonAbilityTable {
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+}
+
+impl AbilityTable for JsonAbilityTable {
+    fn ability_name(&self, link: u16, cmd_index: u8) -> Option<&str> {
+        self.abilities
+            .get(&link)?
+            .get(cmd_index as usize)
+            .map(String::as_str)
+    }
+}
+
+/// Ability tables by the first base build they apply to.
+#[derive(Default)]
+pub struct AbilityTables {
+    tables: Vec<(u32, Box<dyn AbilityTable>)>,
+}
+
+impl AbilityTables {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `table` for `base_build` and the following builds, up to the next table.
+    pub fn register(&mut self, base_build: u32, table: Box<dyn AbilityTable>) {
+        let index = self
+            .tables
+            .partition_point(|(build, _)| *build <= base_build);
+        self.tables.insert(index, (base_build, table));
+    }
+
+    /// Table for a replay of `base_build`, `None` when it predates every table.
+    pub fn for_build(&self, base_build: u32) -> Option<&dyn AbilityTable> {
+        self.tables
+            .iter()
+            .rev()
+            .find(|(build, _)| *build <= base_build)
+            .map(|(_, table)| table.as_ref())
+    }
+}
+
+/// Commands of the game events in game loop order.
+pub fn commands(events: &[Event]) -> Vec<Command> {
+    events.iter().filter_map(Command::from_event).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_protocol_version;
+    use crate::replay::build_replay;
+    use crate::replay::types::fixtures::event;
+
+    #[test]
+    fn it_decodes_commands_with_no_error() {
+        let protocol = load_protocol_version("93272");
+        let mut replay = build_replay("assets/replays/test.SC2Replay", &protocol);
+        let commands = replay.commands();
+        assert_eq!(commands.len(), 667);
+
+        let smart_click = commands
+            .iter()
+            .find(|command| command.ability.is_none())
+            .unwrap();
+        assert_eq!(smart_click.flags.names(), ["smart_click", "user"]);
+        match smart_click.target {
+            CommandTarget::Unit { tag, point, .. } => {
+                assert_eq!(tag, UnitTag::new(10, 1));
+                assert_eq!((point.x, point.y), (163.0, 15.5));
+            }
+            ref target => panic!("Unexpected target {:?}", target),
+        }
+
+        assert!(commands.iter().any(|command| command.flags.is_minimap()));
+        assert!(commands.iter().any(|command| command.flags.is_autocast()
+            && command.flags.contains(CommandFlags::SET_AUTOCAST_ON)));
+
+        let mut tables = AbilityTables::new();
+        let table = JsonAbilityTable::from_json(r#"{"177": ["NexusTrain"]}"#).unwrap();
+        tables.register(80000, Box::new(table));
+        let table = tables
+            .for_build(replay.user_data().version.base_build)
+            .unwrap();
+        assert!(tables.for_build(70000).is_none());
+
+        let first = &commands[0];
+        assert_eq!(first.ability.map(|ability| ability.link), Some(177));
+        assert_eq!(first.ability_name(table), Some("NexusTrain"));
+        assert_eq!(table.ability_name(177, 1), None);
+        assert_eq!(first.target, CommandTarget::None);
+    }
+
+    #[test]
+    fn it_skips_commands_of_out_of_range_users() {
+        let command = |user_id| event("NNet.Game.SCmdEvent", 100, Some(user_id), vec![]);
+        assert_eq!(Command::from_event(&command(3)).map(|c| c.user_id), Some(3));
+        assert_eq!(Command::from_event(&command(259)), None);
+    }
+}
@@ -1,253 +1,527 @@
-extern crate nom;
-
 use super::buffer::BitPackedBuff;
 use super::types::*;
+use super::visitor::{ParsedFieldBuilder, Visitor};
 use crate::protocol::types::Protocol;
 use crate::protocol::types::TypeInfo;
-pub struct BitPackedDecoder;
+use std::error::Error;
+use std::fmt;
 
-pub fn raw_decode(
-    name: &str,
-    type_index: usize,
-    protocol: &Protocol,
-    buffer: &mut BitPackedBuff,
-) -> ParsedField {
-    match protocol.type_infos.get(type_index) {
-        Some(TypeInfo::BitArray { offset, length }) => {
-            // println!("BitArray Field: {}", name);
-            let length = buffer.read_int(*length, *offset) as usize;
-            let name = name.to_string();
-            let data = buffer.read_bit_array(length);
-            let value = Some(ParsedFieldType::BitArray { length, data });
-            // println!("Parsed value: {:?}\n", value);
-
-            ParsedField { name, value }
-        }
-        Some(TypeInfo::Bool) => {
-            // println!("Bool Field: {}", name);
-            let name = name.to_string();
-            let value = Some(ParsedFieldType::Bool(buffer.read_bits(1) != 0));
-            // println!("Parsed value: {:?}\n", value);
+/// Malformed data met by a decoder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The data ended in the middle of a value.
+    UnexpectedEnd,
+    /// A versioned value is not prefixed with the skip byte of its type.
+    UnexpectedSkipByte {
+        expected: u8,
+        found: u8,
+    },
+    UnknownSkipByte(u8),
+    UnknownChoiceTag(isize),
+    /// The type index is not described by the protocol.
+    UnknownType(usize),
+    UnknownEventId(u16),
+    /// A decoded value lacks a field the decoding relies on, e.g. the game loop of an event.
+    MissingField(&'static str),
+    /// A decoded value does not fit the type the field is converted to.
+    OutOfRange(&'static str),
+}
 
-            ParsedField { name, value }
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "Unexpected end of data"),
+            DecodeError::UnexpectedSkipByte { expected, found } => {
+                write!(f, "Expected skip byte {expected}, but found {found}")
+            }
+            DecodeError::UnknownSkipByte(skip) => write!(f, "Unknown skip byte: {skip}"),
+            DecodeError::UnknownChoiceTag(tag) => write!(f, "Unknown choice tag: {tag}"),
+            DecodeError::UnknownType(index) => write!(f, "Unknown type index: {index}"),
+            DecodeError::UnknownEventId(id) => write!(f, "Unknown event id: {id}"),
+            DecodeError::MissingField(name) => write!(f, "Missing field: {name}"),
+            DecodeError::OutOfRange(name) => write!(f, "Value out of range: {name}"),
         }
-        Some(TypeInfo::Int { offset, length }) => {
-            // println!("Int Field: {}", name);
-            let name = name.to_string();
-            let value = Some(ParsedFieldType::Int(buffer.read_int(*length, *offset)));
-            // println!("Parsed value: {:?}\n", value);
+    }
+}
 
-            ParsedField { name, value }
-        }
-        Some(TypeInfo::Optional { type_index }) => {
-            // println!("Optional Field: {}", name);
-            let exists = buffer.read_bits(1) != 0;
-            if exists {
-                raw_decode(name, *type_index, protocol, buffer)
-            } else {
-                let name = name.to_string();
-                let value = None;
-
-                ParsedField { name, value }
-            }
-            // println!("Parsed value: {:?}\n", parsed_field.value);
+impl Error for DecodeError {}
+
+/// Common interface of the decoders driven by the event stream loop, mirroring the
+/// decoder classes of Blizzard's s2protocol.
+///
+/// Decoders do not panic on malformed data: they stop at the first error, reported by `error`.
+pub trait Decoder {
+    /// Walks the instance of the type at `type_index`, reporting every value to `visitor`.
+    fn visit<V: Visitor>(&mut self, type_index: usize, visitor: &mut V);
+    /// Advances past the instance of the type at `type_index` without materializing it.
+    fn skip(&mut self, type_index: usize);
+    fn byte_align(&mut self);
+    fn done(&self) -> bool;
+    fn used_bits(&self) -> usize;
+    /// First error met, after which the values visited are incomplete.
+    fn error(&self) -> Option<DecodeError>;
+
+    /// Decodes the instance of the type at `type_index`, `None` for an absent optional.
+    fn try_instance(&mut self, type_index: usize) -> Result<Option<ParsedFieldType>, DecodeError> {
+        let mut builder = ParsedFieldBuilder::default();
+        self.visit(type_index, &mut builder);
+        match self.error() {
+            Some(error) => Err(error),
+            None => Ok(builder.finish()),
         }
-        Some(TypeInfo::Blob { offset, length }) => {
-            // println!(
-            //     "Blob Field: {}, offset: {}, length: {}",
-            //     name, offset, length
-            // );
-            let length = buffer.read_int(*length, *offset) as usize;
-            // println!("Blob length: {}", length);
-            let name = name.to_string();
-            let bytes = buffer.read_aligned_bytes(length);
-            let chars = String::from_utf8_lossy(&bytes).into_owned();
-            let value = Some(ParsedFieldType::Blob(chars));
-            // println!("Parsed value: {:?}\n", value);
-
-            ParsedField { name, value }
+    }
+
+    /// Same as `try_instance`, panicking on malformed data.
+    fn instance(&mut self, type_index: usize) -> Option<ParsedFieldType> {
+        self.try_instance(type_index)
+            .unwrap_or_else(|error| panic!("Failed to decode type {type_index}: {error}"))
+    }
+}
+
+/// Decoder of the bit-packed format used by game events, message events and init data.
+pub struct BitPackedDecoder<'a, 'p> {
+    buffer: BitPackedBuff<'a>,
+    protocol: &'p Protocol,
+    error: Option<DecodeError>,
+}
+
+impl<'a, 'p> BitPackedDecoder<'a, 'p> {
+    pub fn new(data: &'a [u8], protocol: &'p Protocol) -> Self {
+        Self::from_buffer(BitPackedBuff::new_big_endian(data), protocol)
+    }
+
+    pub fn from_buffer(buffer: BitPackedBuff<'a>, protocol: &'p Protocol) -> Self {
+        BitPackedDecoder {
+            buffer,
+            protocol,
+            error: None,
         }
-        Some(TypeInfo::Array {
-            offset,
-            length,
-            type_index,
-        }) => {
-            // println!("Array Field: {}", name);
-            let name = name.to_string();
-            // println!(
-            //     "Pre: Array Field: {}, length: {}, offset: {}",
-            //     name, *length, *offset,
-            // );
-            let length = buffer.read_int(*length, *offset) as usize;
-            // println!("Array Field: {}, length: {}", name, length);
-            let array =
-                (0..length).map(|_| raw_decode("", *type_index, protocol, buffer).value.unwrap());
-            let value = Some(ParsedFieldType::Array(array.collect()));
-            // println!("Parsed value: {:?}\n", value);
-
-            ParsedField { name, value }
+    }
+
+    pub fn buffer(&self) -> &BitPackedBuff<'a> {
+        &self.buffer
+    }
+
+    fn failed(&self) -> bool {
+        self.error.is_some() || self.buffer.overrun()
+    }
+
+    fn fail(&mut self, error: DecodeError) {
+        self.error.get_or_insert(error);
+    }
+}
+
+impl Decoder for BitPackedDecoder<'_, '_> {
+    fn visit<V: Visitor>(&mut self, type_index: usize, visitor: &mut V) {
+        if self.failed() {
+            return;
         }
-        Some(TypeInfo::Struct { fields }) => {
-            // println!("Struct Field: {}", name);
-            let parsed_fields = fields
-                .iter()
-                .map(|field| raw_decode(&field.name, field.type_index, protocol, buffer));
-            let name = name.to_string();
-            let value = Some(ParsedFieldType::Struct(parsed_fields.collect()));
-            // println!("Parsed value: {:?}\n", value);
-
-            ParsedField { name, value }
+        let protocol = self.protocol;
+        let buffer = &mut self.buffer;
+        match protocol.type_infos.get(type_index) {
+            Some(TypeInfo::BitArray { offset, length }) => {
+                let length = buffer.read_int(*length, *offset) as usize;
+                let data = buffer.read_bit_array(length);
+                visitor.visit_bit_array(length, &data);
+            }
+            Some(TypeInfo::Bool) => visitor.visit_bool(buffer.read_bits(1) != 0),
+            Some(TypeInfo::Int { offset, length }) => {
+                visitor.visit_int(buffer.read_int(*length, *offset))
+            }
+            Some(TypeInfo::Optional { type_index }) => {
+                let exists = buffer.read_bits(1) != 0;
+                if exists {
+                    self.visit(*type_index, visitor);
+                } else {
+                    visitor.visit_none();
+                }
+            }
+            Some(TypeInfo::Blob { offset, length }) => {
+                let length = buffer.read_int(*length, *offset) as usize;
+                visitor.visit_blob(buffer.read_aligned_slice(length));
+            }
+            Some(TypeInfo::Array {
+                offset,
+                length,
+                type_index,
+            }) => {
+                let length = buffer.read_int(*length, *offset) as usize;
+                visitor.enter_array(length);
+                for _ in 0..length {
+                    if self.failed() {
+                        return;
+                    }
+                    self.visit(*type_index, visitor);
+                }
+                visitor.exit_array();
+            }
+            Some(TypeInfo::Struct { fields }) => {
+                visitor.enter_struct();
+                for field in fields {
+                    #[cfg(feature = "tracing")]
+                    let _span = tracing::trace_span!("field", name = %field.name).entered();
+                    visitor.enter_field(&field.name);
+                    self.visit(field.type_index, visitor);
+                    visitor.exit_field();
+                }
+                visitor.exit_struct();
+            }
+            Some(TypeInfo::Choice {
+                offset,
+                length,
+                fields,
+            }) => {
+                let tag = buffer.read_int(*length, *offset);
+                let Some(field) = usize::try_from(tag).ok().and_then(|tag| fields.get(tag)) else {
+                    return self.fail(DecodeError::UnknownChoiceTag(tag));
+                };
+                visitor.enter_choice(&field.name);
+                self.visit(field.type_index, visitor);
+                visitor.exit_choice();
+            }
+            Some(TypeInfo::Null) => visitor.visit_null(),
+            _ => self.fail(DecodeError::UnknownType(type_index)),
         }
-        Some(TypeInfo::Choice {
-            offset,
-            length,
-            fields,
-        }) => {
-            // println!("Choice field: {}", name);
-            let tag = buffer.read_int(*length, *offset) as usize;
-            let field = fields.get(tag).unwrap_or_else(|| {
-                panic!(
-                    "Failed to decode TypeInfo::Choice: tag {} not found in fields",
-                    tag
-                )
-            });
-            raw_decode(name, field.type_index, protocol, buffer)
+    }
+
+    fn skip(&mut self, type_index: usize) {
+        if self.failed() {
+            return;
         }
-        Some(TypeInfo::Null) => ParsedField {
-            name: name.to_string(),
-            value: Some(ParsedFieldType::Null),
-        },
-        others => {
-            panic!("Unknown TypeInfo: {}", others.unwrap());
+        let protocol = self.protocol;
+        let buffer = &mut self.buffer;
+        match protocol.type_infos.get(type_index) {
+            Some(TypeInfo::BitArray { offset, length }) => {
+                let length = buffer.read_int(*length, *offset) as usize;
+                buffer.skip_bits(length);
+            }
+            Some(TypeInfo::Bool) => buffer.skip_bits(1),
+            Some(TypeInfo::Int { offset: _, length }) => buffer.skip_bits(*length),
+            Some(TypeInfo::Optional { type_index }) => {
+                if buffer.read_bits(1) != 0 {
+                    self.skip(*type_index);
+                }
+            }
+            Some(TypeInfo::Blob { offset, length }) => {
+                let length = buffer.read_int(*length, *offset) as usize;
+                buffer.byte_align();
+                buffer.skip_bytes(length);
+            }
+            Some(TypeInfo::Array {
+                offset,
+                length,
+                type_index,
+            }) => {
+                let length = buffer.read_int(*length, *offset) as usize;
+                for _ in 0..length {
+                    if self.failed() {
+                        return;
+                    }
+                    self.skip(*type_index);
+                }
+            }
+            Some(TypeInfo::Struct { fields }) => {
+                for field in fields {
+                    self.skip(field.type_index);
+                }
+            }
+            Some(TypeInfo::Choice {
+                offset,
+                length,
+                fields,
+            }) => {
+                let tag = buffer.read_int(*length, *offset);
+                let Some(field) = usize::try_from(tag).ok().and_then(|tag| fields.get(tag)) else {
+                    return self.fail(DecodeError::UnknownChoiceTag(tag));
+                };
+                self.skip(field.type_index);
+            }
+            Some(TypeInfo::Null) => {}
+            _ => self.fail(DecodeError::UnknownType(type_index)),
         }
     }
+
+    fn byte_align(&mut self) {
+        self.buffer.byte_align();
+    }
+
+    fn done(&self) -> bool {
+        self.buffer.done()
+    }
+
+    fn used_bits(&self) -> usize {
+        self.buffer.used_bits()
+    }
+
+    fn error(&self) -> Option<DecodeError> {
+        self.error
+            .clone()
+            .or_else(|| self.buffer.overrun().then_some(DecodeError::UnexpectedEnd))
+    }
 }
 
-pub fn versioned_decode(
-    name: &str,
-    type_index: usize,
-    protocol: &Protocol,
-    buffer: &mut BitPackedBuff,
-) -> ParsedField {
-    println!("Field name: {:?}", name);
-    match protocol.type_infos.get(type_index) {
-        Some(TypeInfo::Bool) => {
-            println!("Buffer: {}", buffer.data[buffer.byte_index]);
-            buffer.expect_and_skip_byte(6);
-            let name = name.to_string();
-            let value = Some(ParsedFieldType::Bool(buffer.read_bits(8) != 0));
-
-            ParsedField { name, value }
-        }
-        Some(TypeInfo::Optional { type_index }) => {
-            println!("Buffer: {}", buffer.data[buffer.byte_index]);
-            buffer.expect_and_skip_byte(4);
-            let exists = buffer.read_bits(8) != 0;
-            if exists {
-                versioned_decode(name, *type_index, protocol, buffer)
-            } else {
-                let name = name.to_string();
-                let value = None;
-
-                ParsedField { name, value }
-            }
+/// Decoder of the versioned format used by the header, details and tracker events, where
+/// every value is prefixed with a skip byte telling its type.
+pub struct VersionedDecoder<'a, 'p> {
+    buffer: BitPackedBuff<'a>,
+    protocol: &'p Protocol,
+    error: Option<DecodeError>,
+}
+
+impl<'a, 'p> VersionedDecoder<'a, 'p> {
+    pub fn new(data: &'a [u8], protocol: &'p Protocol) -> Self {
+        Self::from_buffer(BitPackedBuff::new_big_endian(data), protocol)
+    }
+
+    pub fn from_buffer(buffer: BitPackedBuff<'a>, protocol: &'p Protocol) -> Self {
+        VersionedDecoder {
+            buffer,
+            protocol,
+            error: None,
         }
-        Some(TypeInfo::Int {
-            offset: _,
-            length: _,
-        }) => {
-            println!("Buffer: {}", buffer.data[buffer.byte_index]);
-            buffer.expect_and_skip_byte(9);
-            let name = name.to_string();
-            let value = Some(ParsedFieldType::Int(buffer.read_var_int()));
-            println!("Parsed value: {:?}", value);
-
-            ParsedField { name, value }
+    }
+
+    pub fn buffer(&self) -> &BitPackedBuff<'a> {
+        &self.buffer
+    }
+
+    /// Skips the next value by its skip byte alone, so that fields unknown to the protocol,
+    /// e.g. those added by a newer build, can be stepped over.
+    pub fn skip_instance(&mut self) {
+        if self.failed() {
+            return;
         }
-        Some(TypeInfo::Blob {
-            offset: _,
-            length: _,
-        }) => {
-            println!("Buffer: {}", buffer.data[buffer.byte_index]);
-            buffer.expect_and_skip_byte(2);
-            let name = name.to_string();
-            let length = buffer.read_var_int() as usize;
-            let bytes = buffer.read_aligned_bytes(length);
-            let chars = String::from_utf8_lossy(&bytes).into_owned();
-            let value = Some(ParsedFieldType::Blob(chars));
-
-            ParsedField { name, value }
+        let buffer = &mut self.buffer;
+        match buffer.read_bits(8) {
+            // array
+            0 => {
+                let length = buffer.read_var_int() as usize;
+                for _ in 0..length {
+                    if self.failed() {
+                        return;
+                    }
+                    self.skip_instance();
+                }
+            }
+            // bit blob
+            1 => {
+                let length = buffer.read_var_int() as usize;
+                buffer.byte_align();
+                buffer.skip_bytes(length.div_ceil(8));
+            }
+            // blob
+            2 => {
+                let length = buffer.read_var_int() as usize;
+                buffer.byte_align();
+                buffer.skip_bytes(length);
+            }
+            // choice
+            3 => {
+                buffer.read_var_int();
+                self.skip_instance();
+            }
+            // optional
+            4 => {
+                if buffer.read_bits(8) != 0 {
+                    self.skip_instance();
+                }
+            }
+            // struct
+            5 => {
+                let length = buffer.read_var_int() as usize;
+                for _ in 0..length {
+                    if self.failed() {
+                        return;
+                    }
+                    self.buffer.read_var_int();
+                    self.skip_instance();
+                }
+            }
+            // u8
+            6 => {
+                buffer.skip_bytes(1);
+            }
+            // u32
+            7 => {
+                buffer.skip_bytes(4);
+            }
+            // u64
+            8 => {
+                buffer.skip_bytes(8);
+            }
+            // vint
+            9 => {
+                buffer.read_var_int();
+            }
+            skip => self.fail(DecodeError::UnknownSkipByte(skip as u8)),
         }
-        Some(TypeInfo::FourCC) => {
-            println!("Buffer: {}", buffer.data[buffer.byte_index]);
-            buffer.expect_and_skip_byte(7);
-            let name = name.to_string();
-            let value = Some(ParsedFieldType::FourCC(buffer.read_aligned_bytes(4)));
+    }
+
+    fn failed(&self) -> bool {
+        self.error.is_some() || self.buffer.overrun()
+    }
 
-            ParsedField { name, value }
+    fn fail(&mut self, error: DecodeError) {
+        self.error.get_or_insert(error);
+    }
+
+    /// Skips the skip byte of a value of the expected type, failing on any other.
+    fn expect_skip_byte(&mut self, expected: u8) -> bool {
+        match self.buffer.expect_and_skip_byte(expected) {
+            Ok(()) => true,
+            Err(Some(found)) => {
+                self.fail(DecodeError::UnexpectedSkipByte { expected, found });
+                false
+            }
+            Err(None) => {
+                self.fail(DecodeError::UnexpectedEnd);
+                false
+            }
         }
-        Some(TypeInfo::Choice {
-            offset: _,
-            length: _,
-            fields,
-        }) => {
-            println!("Buffer: {}", buffer.data[buffer.byte_index]);
-            buffer.expect_and_skip_byte(3);
-            let tag = buffer.read_var_int();
-            let field = fields.iter().find(|f| f.tag == tag).unwrap_or_else(|| {
-                panic!(
-                    "Failed to decode TypeInfo::Choice: tag {} not found in fields",
-                    tag
-                )
-            });
-            versioned_decode(name, field.type_index, protocol, buffer)
+    }
+}
+
+impl Decoder for VersionedDecoder<'_, '_> {
+    fn visit<V: Visitor>(&mut self, type_index: usize, visitor: &mut V) {
+        if self.failed() {
+            return;
         }
-        Some(TypeInfo::Array {
-            offset: _,
-            length: _,
-            type_index,
-        }) => {
-            println!("Buffer: {}", buffer.data[buffer.byte_index]);
-            buffer.expect_and_skip_byte(0);
-            let name = name.to_string();
-            let array_length = buffer.read_var_int() as usize;
-            println!("Array length: {}", array_length);
-            let array = (0..array_length).map(|_| {
-                versioned_decode("", *type_index, protocol, buffer)
-                    .value
-                    .unwrap()
-            });
-            let value = Some(ParsedFieldType::Array(array.collect()));
-
-            ParsedField { name, value }
+        let protocol = self.protocol;
+        let skip_byte = match protocol.type_infos.get(type_index) {
+            Some(TypeInfo::Bool) => 6,
+            Some(TypeInfo::Optional { .. }) => 4,
+            Some(TypeInfo::Int { .. }) => 9,
+            Some(TypeInfo::Blob { .. }) => 2,
+            Some(TypeInfo::FourCC) => 7,
+            Some(TypeInfo::Choice { .. }) => 3,
+            Some(TypeInfo::Array { .. }) => 0,
+            Some(TypeInfo::Struct { .. }) => 5,
+            _ => return self.fail(DecodeError::UnknownType(type_index)),
+        };
+        if !self.expect_skip_byte(skip_byte) {
+            return;
         }
-        Some(TypeInfo::Struct { fields }) => {
-            println!("Buffer: {}", buffer.data[buffer.byte_index]);
-            buffer.expect_and_skip_byte(5);
-            let fields_length = buffer.read_var_int() as usize;
-            let mut parsed_fields: Vec<ParsedField> = Vec::with_capacity(fields_length);
-            let mut i = 0;
-            while i < fields_length {
+        let buffer = &mut self.buffer;
+        match protocol.type_infos.get(type_index) {
+            Some(TypeInfo::Bool) => {
+                visitor.visit_bool(buffer.read_bits(8) != 0);
+            }
+            Some(TypeInfo::Optional { type_index }) => {
+                let exists = buffer.read_bits(8) != 0;
+                if exists {
+                    self.visit(*type_index, visitor);
+                } else {
+                    visitor.visit_none();
+                }
+            }
+            Some(TypeInfo::Int { .. }) => {
+                visitor.visit_int(buffer.read_var_int());
+            }
+            Some(TypeInfo::Blob { .. }) => {
+                let length = buffer.read_var_int() as usize;
+                visitor.visit_blob(buffer.read_aligned_slice(length));
+            }
+            Some(TypeInfo::FourCC) => {
+                visitor.visit_fourcc(buffer.read_aligned_slice(4));
+            }
+            Some(TypeInfo::Choice { fields, .. }) => {
                 let tag = buffer.read_var_int();
-                while tag > fields.get(i).unwrap().tag {
-                    i += 1;
+                let Some(field) = fields.iter().find(|f| f.tag == tag) else {
+                    return self.fail(DecodeError::UnknownChoiceTag(tag));
+                };
+                visitor.enter_choice(&field.name);
+                self.visit(field.type_index, visitor);
+                visitor.exit_choice();
+            }
+            Some(TypeInfo::Array { type_index, .. }) => {
+                let array_length = buffer.read_var_int() as usize;
+                visitor.enter_array(array_length);
+                for _ in 0..array_length {
+                    if self.failed() {
+                        return;
+                    }
+                    self.visit(*type_index, visitor);
                 }
-                let field = fields.get(i).unwrap();
-
-                let parsed_field =
-                    versioned_decode(&field.name, field.type_index, protocol, buffer);
-                parsed_fields.push(parsed_field);
-                i += 1;
+                visitor.exit_array();
             }
-            let name = name.to_string();
-            let value = Some(ParsedFieldType::Struct(parsed_fields));
-
-            ParsedField { name, value }
-        }
-        others => {
-            panic!("Unknown TypeInfo: {}", others.unwrap());
+            Some(TypeInfo::Struct { fields }) => {
+                let fields_length = buffer.read_var_int() as usize;
+                visitor.enter_struct();
+                for _ in 0..fields_length {
+                    if self.failed() {
+                        return;
+                    }
+                    let tag = self.buffer.read_var_int();
+                    match fields.iter().find(|f| f.tag == tag) {
+                        Some(field) => {
+                            #[cfg(feature = "tracing")]
+                            let _span = tracing::trace_span!("field", name = %field.name).entered();
+                            visitor.enter_field(&field.name);
+                            self.visit(field.type_index, visitor);
+                            visitor.exit_field();
+                        }
+                        None => self.skip_instance(),
+                    }
+                }
+                visitor.exit_struct();
+            }
+            _ => {}
         }
     }
+
+    fn skip(&mut self, _type_index: usize) {
+        self.skip_instance();
+    }
+
+    fn byte_align(&mut self) {
+        self.buffer.byte_align();
+    }
+
+    fn done(&self) -> bool {
+        self.buffer.done()
+    }
+
+    fn used_bits(&self) -> usize {
+        self.buffer.used_bits()
+    }
+
+    fn error(&self) -> Option<DecodeError> {
+        self.error
+            .clone()
+            .or_else(|| self.buffer.overrun().then_some(DecodeError::UnexpectedEnd))
+    }
+}
+
+pub fn raw_decode(
+    name: &str,
+    type_index: usize,
+    protocol: &Protocol,
+    buffer: &mut BitPackedBuff,
+) -> ParsedField {
+    let mut decoder = BitPackedDecoder::from_buffer(*buffer, protocol);
+    let value = decoder.instance(type_index);
+    *buffer = decoder.buffer;
+
+    ParsedField {
+        name: name.to_string(),
+        value,
+    }
+}
+
+pub fn versioned_decode(
+    name: &str,
+    type_index: usize,
+    protocol: &Protocol,
+    buffer: &mut BitPackedBuff,
+) -> ParsedField {
+    let mut decoder = VersionedDecoder::from_buffer(*buffer, protocol);
+    let value = decoder.instance(type_index);
+    *buffer = decoder.buffer;
+
+    ParsedField {
+        name: name.to_string(),
+        value,
+    }
 }
 
 #[cfg(test)]
@@ -255,18 +529,147 @@ mod tests {
     use super::*;
     use crate::load_protocol_version;
 
-    #[test]
-    fn it_parse_user_data_with_no_error() {
-        let protocol = load_protocol_version("93272");
-        let index: usize = protocol.replay_header_type_index.unwrap();
-        let input: &[u8] = &[
+    fn user_data_fixture() -> &'static [u8] {
+        &[
             5, 18, 0, 2, 44, 83, 116, 97, 114, 67, 114, 97, 102, 116, 32, 73, 73, 32, 114, 101,
             112, 108, 97, 121, 27, 49, 49, 2, 5, 12, 0, 9, 2, 2, 9, 10, 4, 9, 0, 6, 9, 28, 8, 9,
             176, 177, 11, 10, 9, 176, 177, 11, 4, 9, 4, 6, 9, 202, 183, 1, 8, 6, 1, 10, 5, 2, 2, 2,
             32, 82, 146, 10, 157, 137, 199, 246, 50, 53, 148, 93, 16, 243, 199, 60, 100, 12, 9,
             176, 177, 11, 14, 5, 2, 2, 2, 32, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 16,
             6, 0,
-        ];
+        ]
+    }
+
+    #[derive(Default)]
+    struct CountingVisitor {
+        ints: usize,
+        fields: Vec<String>,
+    }
+
+    impl Visitor for CountingVisitor {
+        fn visit_int(&mut self, _value: isize) {
+            self.ints += 1;
+        }
+
+        fn enter_field(&mut self, name: &str) {
+            self.fields.push(name.to_string());
+        }
+    }
+
+    #[test]
+    fn it_tracks_used_bits_in_bit_packed_decoder() {
+        let protocol = load_protocol_version("93272");
+        let input: &[u8] = &[44];
+        let mut decoder = BitPackedDecoder::new(input, &protocol);
+        assert_eq!(decoder.instance(0), Some(ParsedFieldType::Int(44)));
+        assert_eq!(decoder.used_bits(), 7);
+        assert!(!decoder.done());
+        decoder.byte_align();
+        assert_eq!(decoder.used_bits(), 8);
+        assert!(decoder.done());
+    }
+
+    #[test]
+    fn it_decodes_choices_as_single_variant_structs() {
+        let protocol = load_protocol_version("93272");
+        // Selection remove mask: a choice of None, Mask, OneIndices and ZeroIndices.
+        let input: &[u8] = &[0];
+        let mut decoder = BitPackedDecoder::new(input, &protocol);
+        let mask = decoder.instance(104).unwrap();
+        assert_eq!(
+            mask,
+            ParsedFieldType::Struct(vec![ParsedField {
+                name: String::from("None"),
+                value: Some(ParsedFieldType::Null),
+            }])
+        );
+        assert_eq!(mask.as_choice(), Some(("None", &ParsedFieldType::Null)));
+        assert_eq!(decoder.used_bits(), 2);
+    }
+
+    #[test]
+    fn it_tracks_used_bits_in_versioned_decoder() {
+        let protocol = load_protocol_version("93272");
+        let input: &[u8] = &[9, 44];
+        let mut decoder = VersionedDecoder::new(input, &protocol);
+        assert_eq!(decoder.instance(0), Some(ParsedFieldType::Int(22)));
+        assert_eq!(decoder.used_bits(), 16);
+        assert!(decoder.done());
+    }
+
+    #[test]
+    fn it_visits_user_data_without_building_a_tree() {
+        let protocol = load_protocol_version("93272");
+        let index: usize = protocol.replay_header_type_index.unwrap();
+        let mut decoder = VersionedDecoder::new(user_data_fixture(), &protocol);
+        let mut visitor = CountingVisitor::default();
+        decoder.visit(index, &mut visitor);
+        assert_eq!(visitor.ints, 9);
+        assert_eq!(visitor.fields.len(), 17);
+        assert_eq!(visitor.fields[3], "m_major");
+        assert!(decoder.done());
+    }
+
+    #[test]
+    fn it_skips_user_data() {
+        let protocol = load_protocol_version("93272");
+        let index: usize = protocol.replay_header_type_index.unwrap();
+        let mut decoder = VersionedDecoder::new(user_data_fixture(), &protocol);
+        decoder.skip(index);
+        assert_eq!(decoder.used_bits(), user_data_fixture().len() * 8);
+        assert!(decoder.done());
+    }
+
+    #[test]
+    fn it_reports_truncated_and_malformed_data_with_no_panic() {
+        let protocol = load_protocol_version("93272");
+        let index: usize = protocol.replay_header_type_index.unwrap();
+        let input = user_data_fixture();
+        let mut decoder = VersionedDecoder::new(&input[..input.len() / 2], &protocol);
+        assert_eq!(decoder.try_instance(index), Err(DecodeError::UnexpectedEnd));
+
+        let mut decoder = VersionedDecoder::new(&[42, 0], &protocol);
+        assert_eq!(
+            decoder.try_instance(index),
+            Err(DecodeError::UnexpectedSkipByte {
+                expected: 5,
+                found: 42
+            })
+        );
+    }
+
+    #[test]
+    fn it_skips_fields_unknown_to_the_protocol() {
+        let mut protocol = load_protocol_version("93272");
+        let index: usize = protocol.replay_header_type_index.unwrap();
+        if let Some(TypeInfo::Struct { fields }) = protocol.type_infos.get_mut(index) {
+            fields.retain(|f| f.name != "m_version" && f.name != "m_ngdpRootKey");
+        }
+        let mut decoder = VersionedDecoder::new(user_data_fixture(), &protocol);
+        let Some(ParsedFieldType::Struct(fields)) = decoder.instance(index) else {
+            panic!("Failed to decode user data")
+        };
+        let names: Vec<&str> = fields.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "m_signature",
+                "m_type",
+                "m_elapsedGameLoops",
+                "m_useScaledTime",
+                "m_dataBuildNum",
+                "m_replayCompatibilityHash",
+                "m_ngdpRootKeyIsDevData"
+            ]
+        );
+        assert!(decoder.done());
+    }
+
+    #[test]
+    fn it_parse_user_data_with_no_error() {
+        let protocol = load_protocol_version("93272");
+        let index: usize = protocol.replay_header_type_index.unwrap();
+        let input = user_data_fixture();
         let mut buffer = BitPackedBuff::new_big_endian(input);
         let user_data = versioned_decode("UserData", index, &protocol, &mut buffer);
         assert_eq!(
@@ -276,9 +679,7 @@ mod tests {
                 value: Some(ParsedFieldType::Struct(vec![
                     ParsedField {
                         name: String::from("m_signature"),
-                        value: Some(ParsedFieldType::Blob(String::from(
-                            "StarCraft II replay\u{1b}11"
-                        )))
+                        value: Some(ParsedFieldType::Blob(b"StarCraft II replay\x1b11".to_vec()))
                     },
                     ParsedField {
                         name: String::from("m_version"),
@@ -325,9 +726,9 @@ mod tests {
                         name: String::from("m_ngdpRootKey"),
                         value: Some(ParsedFieldType::Struct(vec![ParsedField {
                             name: String::from("m_data"),
-                            value: Some(ParsedFieldType::Blob(String::from(
-                                "R�\n����25�]\u{10}��<d"
-                            )))
+                            value: Some(ParsedFieldType::Blob(
+                                b"R\x92\n\x9d\x89\xc7\xf625\x94]\x10\xf3\xc7<d".to_vec()
+                            ))
                         }]))
                     },
                     ParsedField {
@@ -338,9 +739,9 @@ mod tests {
                         name: String::from("m_replayCompatibilityHash"),
                         value: Some(ParsedFieldType::Struct(vec![ParsedField {
                             name: String::from("m_data"),
-                            value: Some(ParsedFieldType::Blob(String::from(
-                                "\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0"
-                            )))
+                            value: Some(ParsedFieldType::Blob(
+                                b"\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0".to_vec()
+                            ))
                         }]))
                     },
                     ParsedField {
@@ -400,14 +801,14 @@ mod tests {
                     ParsedField { name: String::from("m_playerList"),
                         value: Some(ParsedFieldType::Array(vec![
                             ParsedFieldType::Struct(vec![
-                                ParsedField { name: String::from("m_name"), value: Some(ParsedFieldType::Blob(String::from("gumiho"))) },
+                                ParsedField { name: String::from("m_name"), value: Some(ParsedFieldType::Blob(b"gumiho".to_vec())) },
                                 ParsedField { name: String::from("m_toon"), value: Some(ParsedFieldType::Struct(vec![
                                     ParsedField { name: String::from("m_region"), value: Some(ParsedFieldType::Int(2)) },
                                     ParsedField { name: String::from("m_programId"), value: Some(ParsedFieldType::FourCC(vec![0, 0, 83, 50])) },
                                     ParsedField { name: String::from("m_realm"), value: Some(ParsedFieldType::Int(1)) },
                                     ParsedField { name: String::from("m_id"), value: Some(ParsedFieldType::Int(3885137)) }
                                 ])) },
-                                ParsedField { name: String::from("m_race"), value: Some(ParsedFieldType::Blob(String::from("Terran"))) },
+                                ParsedField { name: String::from("m_race"), value: Some(ParsedFieldType::Blob(b"Terran".to_vec())) },
                                 ParsedField { name: String::from("m_color"), value: Some(ParsedFieldType::Struct(vec![
                                     ParsedField { name: String::from("m_a"), value: Some(ParsedFieldType::Int(255)) },
                                     ParsedField { name: String::from("m_r"), value: Some(ParsedFieldType::Int(180)) },
@@ -420,17 +821,17 @@ mod tests {
                                 ParsedField { name: String::from("m_observe"), value: Some(ParsedFieldType::Int(0)) },
                                 ParsedField { name: String::from("m_result"), value: Some(ParsedFieldType::Int(1)) },
                                 ParsedField { name: String::from("m_workingSetSlotId"), value: Some(ParsedFieldType::Int(11)) },
-                                ParsedField { name: String::from("m_hero"), value: Some(ParsedFieldType::Blob(String::from(""))) }
+                                ParsedField { name: String::from("m_hero"), value: Some(ParsedFieldType::Blob(b"".to_vec())) }
                             ]),
                             ParsedFieldType::Struct(vec![
-                                ParsedField { name: String::from("m_name"), value: Some(ParsedFieldType::Blob(String::from("&lt;mlem&gt;<sp/>LiquidClem"))) },
+                                ParsedField { name: String::from("m_name"), value: Some(ParsedFieldType::Blob(b"&lt;mlem&gt;<sp/>LiquidClem".to_vec())) },
                                 ParsedField { name: String::from("m_toon"), value: Some(ParsedFieldType::Struct(vec![
                                     ParsedField { name: String::from("m_region"), value: Some(ParsedFieldType::Int(2)) },
                                     ParsedField { name: String::from("m_programId"), value: Some(ParsedFieldType::FourCC(vec![0, 0, 83, 50])) },
                                     ParsedField { name: String::from("m_realm"), value: Some(ParsedFieldType::Int(1)) },
                                     ParsedField { name: String::from("m_id"), value: Some(ParsedFieldType::Int(3141896)) }
                                 ])) },
-                                ParsedField { name: String::from("m_race"), value: Some(ParsedFieldType::Blob(String::from("Protoss"))) },
+                                ParsedField { name: String::from("m_race"), value: Some(ParsedFieldType::Blob(b"Protoss".to_vec())) },
                                 ParsedField { name: String::from("m_color"), value: Some(ParsedFieldType::Struct(vec![
                                     ParsedField { name: String::from("m_a"), value: Some(ParsedFieldType::Int(255)) },
                                     ParsedField { name: String::from("m_r"), value: Some(ParsedFieldType::Int(0)) },
@@ -443,29 +844,29 @@ mod tests {
                                 ParsedField { name: String::from("m_observe"), value: Some(ParsedFieldType::Int(0)) },
                                 ParsedField { name: String::from("m_result"), value: Some(ParsedFieldType::Int(2)) },
                                 ParsedField { name: String::from("m_workingSetSlotId"), value: Some(ParsedFieldType::Int(14)) },
-                                ParsedField { name: String::from("m_hero"), value: Some(ParsedFieldType::Blob(String::from(""))) }])
+                                ParsedField { name: String::from("m_hero"), value: Some(ParsedFieldType::Blob(b"".to_vec())) }])
                             ])) },
-                            ParsedField { name: String::from("m_title"), value: Some(ParsedFieldType::Blob(String::from("Abyssal Reef LE"))) },
-                            ParsedField { name: String::from("m_difficulty"), value: Some(ParsedFieldType::Blob(String::from(""))) },
+                            ParsedField { name: String::from("m_title"), value: Some(ParsedFieldType::Blob(b"Abyssal Reef LE".to_vec())) },
+                            ParsedField { name: String::from("m_difficulty"), value: Some(ParsedFieldType::Blob(b"".to_vec())) },
                             ParsedField { name: String::from("m_thumbnail"), value: Some(ParsedFieldType::Struct(vec![
-                                ParsedField { name: String::from("m_file"), value: Some(ParsedFieldType::Blob(String::from("Minimap.tga"))) }
+                                ParsedField { name: String::from("m_file"), value: Some(ParsedFieldType::Blob(b"Minimap.tga".to_vec())) }
                             ])) },
                             ParsedField { name: String::from("m_isBlizzardMap"), value: Some(ParsedFieldType::Bool(true)) },
                             ParsedField { name: String::from("m_timeUTC"), value: Some(ParsedFieldType::Int(133775741252511615)) },
                             ParsedField { name: String::from("m_timeLocalOffset"), value: Some(ParsedFieldType::Int(36000000000)) },
-                            ParsedField { name: String::from("m_description"), value: Some(ParsedFieldType::Blob(String::from(""))) },
-                            ParsedField { name: String::from("m_imageFilePath"), value: Some(ParsedFieldType::Blob(String::from(""))) },
-                            ParsedField { name: String::from("m_mapFileName"), value: Some(ParsedFieldType::Blob(String::from(""))) },
+                            ParsedField { name: String::from("m_description"), value: Some(ParsedFieldType::Blob(b"".to_vec())) },
+                            ParsedField { name: String::from("m_imageFilePath"), value: Some(ParsedFieldType::Blob(b"".to_vec())) },
+                            ParsedField { name: String::from("m_mapFileName"), value: Some(ParsedFieldType::Blob(b"".to_vec())) },
                             ParsedField { name: String::from("m_cacheHandles"), value: Some(ParsedFieldType::Array(vec![
-                                ParsedFieldType::Blob(String::from("s2ma\0\0EUm�\u{15}\u{3}���VV6\u{b}o\u{2}}��i�\u{19}��cW��\u{15}�Ty9��")),
-                                ParsedFieldType::Blob(String::from("s2ma\0\0EUB\u{1c}���a�e-#�s]��\u{12}�dB(#^zy~���}�\u{e}")),
-                                ParsedFieldType::Blob(String::from("s2ma\0\0EUf\t82\u{12}�S���x|���������\\��0\u{7a7}�NPZ\u{4}")),
-                                ParsedFieldType::Blob(String::from("s2ma\0\0EU�-�HĄőT'\u{b}�J��t��Gb\u{1c}z�d1�f�;@")),
-                                ParsedFieldType::Blob(String::from("s2ma\0\0EU�Vo���a\u{1a}��ʂq�$C\u{13}MLR�=2��\u{11}\u{5}GD���")),
-                                ParsedFieldType::Blob(String::from("s2ma\0\0EUl\u{5}RrGDق`Y�I�\u{1e}�&�\u{11};n;l,�c�\r��c~\u{1f}")),
-                                ParsedFieldType::Blob(String::from("s2ma\0\0EUU.,L�\u{1}��#�Z8}��\u{13}CPD�tқ\u{1c}��Е�܋")),
-                                ParsedFieldType::Blob(String::from("s2ma\0\0EU\u{7f}AA\u{1a}����d@�*V3H�S�-*h\u{11}/\u{1}\u{4}�����Z�")),
-                                ParsedFieldType::Blob(String::from("s2ma\0\0EU+�eu\u{10}�\u{1f}z\u{11}��s�2mG\u{14}�>^ra������*�"))
+                                ParsedFieldType::Blob(b"s2ma\0\0EUm\xe4\x15\x03\xba\xcc\xd0VV6\x0bo\x02}\xb8\x81i\xfa\x19\x89\xbbcW\xb1\xb2\x15\xa2Ty9\xf5\xfb".to_vec()),
+                                ParsedFieldType::Blob(b"s2ma\0\0EUB\x1c\x8a\xa0\xf3a\x9be-#\xa2s]\xfe\xe8\x12\xabdB(#^zy~\xde\xcf\xe8\xb6}\xa3\x0e".to_vec()),
+                                ParsedFieldType::Blob(b"s2ma\0\0EUf\t82\x12\x84S\xef\xff\xbbx|\x80\xb7\xd3\xee\xc1\xad\x81\xbd\xe5\\\x83\xc90\xde\xa7\x9cNPZ\x04".to_vec()),
+                                ParsedFieldType::Blob(b"s2ma\0\0EU\xd9-\xfcH\xc4\x84\xc5\x91T'\x0b\x92J\xd7\xd5t\x84\xf2\xab\x9aGb\x1cz\xb1d1\xbff\xc5;@".to_vec()),
+                                ParsedFieldType::Blob(b"s2ma\0\0EU\x9cVo\xa1\xba\xada\x1a\x9b\xc5\xca\x82q\xc7$C\x13MLR\x98=2\xcb\xca\x11\x05GD\xcd\xfd\x8c".to_vec()),
+                                ParsedFieldType::Blob(b"s2ma\0\0EUl\x05RrGD\xd9\x82`Y\xfaI\xfc\x1e\xd2&\xf5\x11;n;l,\x80c\xe0\r\xbc\xbcc~\x1f".to_vec()),
+                                ParsedFieldType::Blob(b"s2ma\0\0EUU.,L\xd4\x01\xb3\xce#\x9aZ8}\xca\xf2\xb2\x13CPD\xf1t\xd2\x9b\x1c\xe1\xc6\xd0\x95\xe0\xdc\x8b".to_vec()),
+                                ParsedFieldType::Blob(b"s2ma\0\0EU\x7fAA\x1a\xa5\x97\xf4\xb4d@\xd4*V3H\xbfS\x82-*h\x11/\x01\x04\xf9\xb8\x91\xf6\xf0Z\xe1".to_vec()),
+                                ParsedFieldType::Blob(b"s2ma\0\0EU+\xa4eu\x10\xef\x1fz\x11\xac\xacs\xaf2mG\x14\xe8>^ra\xf9\xb1\xf1\xf1\xb9\xa7\xcb\xff*\xfa".to_vec())
                             ])) },
                             ParsedField { name: String::from("m_miniSave"), value: Some(ParsedFieldType::Bool(false)) },
                             ParsedField { name: String::from("m_gameSpeed"), value: Some(ParsedFieldType::Int(4)) },
@@ -0,0 +1,234 @@
+use super::decoders::DecodeError;
+use super::types::*;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Number of 100ns intervals between 1601-01-01, the Windows file time epoch, and 1970-01-01.
+const FILETIME_UNIX_EPOCH_OFFSET: i64 = 116_444_736_000_000_000;
+
+/// Typed content of `replay.details`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Details {
+    pub players: Vec<PlayerDetails>,
+    pub title: String,
+    pub description: String,
+    pub image_file_path: String,
+    pub map_file_name: String,
+    pub is_blizzard_map: bool,
+    pub time_utc: SystemTime,
+    /// Offset of the local time zone of the recording client to UTC, in seconds.
+    pub time_local_offset: i64,
+    pub cache_handles: Vec<CacheHandle>,
+    pub game_speed: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerDetails {
+    pub name: String,
+    pub toon: Toon,
+    pub race: String,
+    pub color: Color,
+    pub control: u8,
+    pub team_id: u8,
+    pub handicap: u32,
+    pub observe: u8,
+    pub result: GameResult,
+    pub working_set_slot_id: Option<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Toon {
+    pub region: u8,
+    pub program_id: String,
+    pub realm: u32,
+    pub id: u64,
+}
+
+impl Toon {
+    /// Toon handle in the `region-programId-realm-id` form, e.g. `2-S2-1-3885137`.
+    pub fn handle(&self) -> String {
+        format!(
+            "{}-{}-{}-{}",
+            self.region, self.program_id, self.realm, self.id
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Color {
+    pub a: u8,
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameResult {
+    Undecided,
+    Victory,
+    Defeat,
+    Tie,
+}
+
+impl From<isize> for GameResult {
+    fn from(value: isize) -> Self {
+        match value {
+            1 => GameResult::Victory,
+            2 => GameResult::Defeat,
+            3 => GameResult::Tie,
+            _ => GameResult::Undecided,
+        }
+    }
+}
+
+fn filetime_to_system_time(filetime: i64) -> Result<SystemTime, DecodeError> {
+    const OUT_OF_RANGE: DecodeError = DecodeError::OutOfRange("m_timeUTC");
+    let intervals = filetime
+        .checked_sub(FILETIME_UNIX_EPOCH_OFFSET)
+        .ok_or(OUT_OF_RANGE)?;
+    let magnitude = intervals.unsigned_abs();
+    let offset = Duration::new(
+        magnitude / 10_000_000,
+        (magnitude % 10_000_000) as u32 * 100,
+    );
+    let time = if intervals >= 0 {
+        UNIX_EPOCH.checked_add(offset)
+    } else {
+        UNIX_EPOCH.checked_sub(offset)
+    };
+    time.ok_or(OUT_OF_RANGE)
+}
+
+impl From<&ParsedFieldType> for Toon {
+    fn from(value: &ParsedFieldType) -> Self {
+        Toon {
+            region: int(value, "m_region") as u8,
+            program_id: value
+                .field("m_programId")
+                .and_then(ParsedFieldType::as_fourcc)
+                .unwrap_or_default(),
+            realm: int(value, "m_realm") as u32,
+            id: int(value, "m_id") as u64,
+        }
+    }
+}
+
+impl From<&ParsedFieldType> for Color {
+    fn from(value: &ParsedFieldType) -> Self {
+        Color {
+            a: int(value, "m_a") as u8,
+            r: int(value, "m_r") as u8,
+            g: int(value, "m_g") as u8,
+            b: int(value, "m_b") as u8,
+        }
+    }
+}
+
+impl TryFrom<&ParsedFieldType> for PlayerDetails {
+    type Error = DecodeError;
+
+    fn try_from(value: &ParsedFieldType) -> Result<Self, Self::Error> {
+        Ok(PlayerDetails {
+            name: string(value, "m_name"),
+            toon: value
+                .field("m_toon")
+                .map(Toon::from)
+                .ok_or(DecodeError::MissingField("m_toon"))?,
+            race: string(value, "m_race"),
+            color: value
+                .field("m_color")
+                .map(Color::from)
+                .ok_or(DecodeError::MissingField("m_color"))?,
+            control: int(value, "m_control") as u8,
+            team_id: int(value, "m_teamId") as u8,
+            handicap: int(value, "m_handicap") as u32,
+            observe: int(value, "m_observe") as u8,
+            result: GameResult::from(int(value, "m_result")),
+            working_set_slot_id: value
+                .field("m_workingSetSlotId")
+                .and_then(ParsedFieldType::as_int)
+                .map(|id| id as u8),
+        })
+    }
+}
+
+impl TryFrom<&ParsedFieldType> for Details {
+    type Error = DecodeError;
+
+    fn try_from(value: &ParsedFieldType) -> Result<Self, Self::Error> {
+        let players = value
+            .field("m_playerList")
+            .and_then(ParsedFieldType::as_array)
+            .unwrap_or_default()
+            .iter()
+            .map(PlayerDetails::try_from)
+            .collect::<Result<_, _>>()?;
+        let cache_handles = value
+            .field("m_cacheHandles")
+            .and_then(ParsedFieldType::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|handle| handle.as_blob().and_then(CacheHandle::from_bytes))
+            .collect();
+
+        Ok(Details {
+            players,
+            title: string(value, "m_title"),
+            description: string(value, "m_description"),
+            image_file_path: string(value, "m_imageFilePath"),
+            map_file_name: string(value, "m_mapFileName"),
+            is_blizzard_map: boolean(value, "m_isBlizzardMap"),
+            time_utc: filetime_to_system_time(int(value, "m_timeUTC") as i64)?,
+            time_local_offset: int(value, "m_timeLocalOffset") as i64 / 10_000_000,
+            cache_handles,
+            game_speed: int(value, "m_gameSpeed") as u8,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_protocol_version;
+    use crate::replay::build_replay;
+
+    #[test]
+    fn it_decodes_details_with_no_error() {
+        let protocol = load_protocol_version("93272");
+        let mut replay = build_replay("assets/replays/test.SC2Replay", &protocol);
+        let details = replay.details();
+        assert_eq!(details.title, "Abyssal Reef LE");
+        assert_eq!(details.players.len(), 2);
+        assert_eq!(details.players[0].name, "gumiho");
+        assert_eq!(details.players[0].toon.handle(), "2-S2-1-3885137");
+        assert_eq!(details.players[0].result, GameResult::Victory);
+        assert_eq!(details.players[1].race, "Protoss");
+        assert_eq!(details.players[1].working_set_slot_id, Some(14));
+        assert_eq!(
+            details.time_utc,
+            UNIX_EPOCH + Duration::from_nanos(1_733_100_525_251_161_500)
+        );
+        assert_eq!(details.time_local_offset, 3600);
+        assert_eq!(details.cache_handles.len(), 9);
+        assert_eq!(
+            details.cache_handles[0].url(),
+            "http://eu.depot.battle.net:1119/\
+             6de41503baccd05656360b6f027db88169fa1989bb6357b1b215a2547939f5fb.s2ma"
+        );
+    }
+
+    #[test]
+    fn it_reports_missing_player_fields_and_out_of_range_times() {
+        let player = ParsedFieldType::Struct(vec![ParsedField {
+            name: String::from("m_name"),
+            value: Some(ParsedFieldType::Blob(b"gumiho".to_vec())),
+        }]);
+        assert_eq!(
+            PlayerDetails::try_from(&player),
+            Err(DecodeError::MissingField("m_toon"))
+        );
+        assert_eq!(
+            filetime_to_system_time(i64::MIN),
+            Err(DecodeError::OutOfRange("m_timeUTC"))
+        );
+    }
+}
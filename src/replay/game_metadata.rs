@@ -0,0 +1,67 @@
+use serde::Deserialize;
+
+/// Typed content of `replay.gamemetadata.json`, only written by recent clients.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct GameMetadata {
+    pub title: String,
+    pub game_version: String,
+    pub data_build: String,
+    pub base_build: String,
+    pub data_version: Option<String>,
+    /// Length of the game in game seconds of 16 game loops, not in real seconds.
+    pub duration: u32,
+    #[serde(default)]
+    pub is_not_available: bool,
+    pub players: Vec<PlayerMetadata>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PlayerMetadata {
+    #[serde(rename = "PlayerID")]
+    pub player_id: u8,
+    /// `Win`, `Loss`, `Tie` or `Undecided`.
+    pub result: String,
+    /// Matchmaking rating, only present for ladder games.
+    #[serde(rename = "MMR")]
+    pub mmr: Option<i32>,
+    #[serde(rename = "APM")]
+    pub apm: f64,
+    /// Race picked in the lobby, `Terr`, `Zerg`, `Prot` or `Rand`.
+    pub selected_race: String,
+    /// Race actually played, which differs from the selected one for random players.
+    pub assigned_race: Option<String>,
+}
+
+impl PlayerMetadata {
+    pub fn is_winner(&self) -> bool {
+        self.result == "Win"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::load_protocol_version;
+    use crate::replay::build_replay;
+
+    #[test]
+    fn it_decodes_game_metadata_with_no_error() {
+        let protocol = load_protocol_version("93272");
+        let mut replay = build_replay("assets/replays/test.SC2Replay", &protocol);
+        let metadata = replay
+            .game_metadata()
+            .unwrap()
+            .expect("Failed to find game metadata");
+        assert_eq!(metadata.title, "Abyssal Reef LE");
+        assert_eq!(metadata.game_version, "5.0.14.93272");
+        assert_eq!(metadata.data_build, "93272");
+        assert_eq!(metadata.duration, 734);
+        assert_eq!(metadata.players.len(), 2);
+        assert!(metadata.players[0].is_winner());
+        assert_eq!(metadata.players[0].apm, 314.0);
+        assert_eq!(metadata.players[0].mmr, None);
+        assert_eq!(metadata.players[1].result, "Loss");
+        assert_eq!(metadata.players[1].selected_race, "Prot");
+    }
+}
@@ -0,0 +1,245 @@
+use super::types::*;
+use std::fmt;
+use std::time::Duration;
+
+/// Game event changing the game speed during a game.
+pub const SET_GAME_SPEED_EVENT: &str = "NNet.Game.SSetAbsoluteGameSpeedEvent";
+
+/// Game speed setting, from `m_gameSpeed` or `SSetAbsoluteGameSpeedEvent`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameSpeed {
+    Slower,
+    Slow,
+    Normal,
+    Fast,
+    Faster,
+    Unknown(u8),
+}
+
+impl From<isize> for GameSpeed {
+    fn from(value: isize) -> Self {
+        match value {
+            0 => GameSpeed::Slower,
+            1 => GameSpeed::Slow,
+            2 => GameSpeed::Normal,
+            3 => GameSpeed::Fast,
+            4 => GameSpeed::Faster,
+            other => GameSpeed::Unknown(other as u8),
+        }
+    }
+}
+
+impl GameSpeed {
+    /// Game seconds elapsing per real second, taking unknown speeds as "Normal".
+    pub fn multiplier(&self) -> f64 {
+        match self {
+            GameSpeed::Slower => 0.6,
+            GameSpeed::Slow => 0.8,
+            GameSpeed::Normal | GameSpeed::Unknown(_) => 1.0,
+            GameSpeed::Fast => 1.2,
+            GameSpeed::Faster => 1.4,
+        }
+    }
+
+    fn loops_per_real_second(&self) -> f64 {
+        GAME_LOOPS_PER_SECOND * self.multiplier()
+    }
+}
+
+/// Game loops per second of the in-game clock, which counts real seconds at the "Faster" game
+/// speed when `m_useScaledTime` is set.
+pub fn loops_per_game_second(use_scaled_time: bool) -> f64 {
+    if use_scaled_time {
+        GAME_LOOPS_PER_REAL_SECOND
+    } else {
+        GAME_LOOPS_PER_SECOND
+    }
+}
+
+/// Point of a replay as a game loop, the in-game clock and the real time elapsed.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct GameTime {
+    pub game_loop: usize,
+    /// Seconds shown by the in-game clock.
+    pub game_seconds: f64,
+    /// Seconds elapsed for the players, following the changes of game speed.
+    pub real_seconds: f64,
+}
+
+impl GameTime {
+    pub fn game(&self) -> Duration {
+        Duration::from_secs_f64(self.game_seconds)
+    }
+
+    pub fn real(&self) -> Duration {
+        Duration::from_secs_f64(self.real_seconds)
+    }
+}
+
+/// Formats the in-game clock as `mm:ss`.
+impl fmt::Display for GameTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let seconds = self.game_seconds as u64;
+        write!(f, "{:02}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+/// Anything happening at a game loop, such as an event, placed in time by a `GameClock`.
+pub trait Timed {
+    fn game_loop(&self) -> usize;
+
+    fn time(&self, clock: &GameClock) -> GameTime {
+        clock.time(self.game_loop())
+    }
+}
+
+/// Converts between game loops and times for a replay.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameClock {
+    pub use_scaled_time: bool,
+    /// Game speed from each game loop on, starting at 0.
+    speeds: Vec<(usize, GameSpeed)>,
+}
+
+/// Clock of ladder games, played at the "Faster" speed with scaled time.
+impl Default for GameClock {
+    fn default() -> Self {
+        GameClock::new(GameSpeed::Faster, true)
+    }
+}
+
+impl GameClock {
+    pub fn new(speed: GameSpeed, use_scaled_time: bool) -> Self {
+        GameClock {
+            use_scaled_time,
+            speeds: vec![(0, speed)],
+        }
+    }
+
+    /// Clock starting at `speed` and following the `SSetAbsoluteGameSpeedEvent`s of the game
+    /// events.
+    pub fn from_events(speed: GameSpeed, use_scaled_time: bool, events: &[Event]) -> Self {
+        let mut clock = GameClock::new(speed, use_scaled_time);
+        let changes = events
+            .iter()
+            .filter(|event| event.name == SET_GAME_SPEED_EVENT);
+        for event in changes {
+            let Some(speed) = event
+                .data
+                .field("m_speed")
+                .and_then(ParsedFieldType::as_int)
+            else {
+                continue;
+            };
+            let speed = GameSpeed::from(speed);
+            match clock.speeds.last_mut() {
+                Some(last) if last.0 == event.game_loop => last.1 = speed,
+                _ => clock.speeds.push((event.game_loop, speed)),
+            }
+        }
+        clock
+    }
+
+    pub fn speed_at(&self, game_loop: usize) -> GameSpeed {
+        self.speeds
+            .iter()
+            .rev()
+            .find(|(start, _)| *start <= game_loop)
+            .map_or(GameSpeed::Faster, |(_, speed)| *speed)
+    }
+
+    pub fn loops_per_game_second(&self) -> f64 {
+        loops_per_game_second(self.use_scaled_time)
+    }
+
+    pub fn time(&self, game_loop: usize) -> GameTime {
+        GameTime {
+            game_loop,
+            game_seconds: game_loop as f64 / self.loops_per_game_second(),
+            real_seconds: self.real_seconds(game_loop),
+        }
+    }
+
+    /// First game loop at which the in-game clock reaches `seconds`.
+    pub fn game_loop_at_game_seconds(&self, seconds: f64) -> usize {
+        (seconds * self.loops_per_game_second()).ceil() as usize
+    }
+
+    /// First game loop at which `seconds` have elapsed for the players.
+    pub fn game_loop_at_real_seconds(&self, seconds: f64) -> usize {
+        let mut elapsed = 0.0;
+        for (index, (start, speed)) in self.speeds.iter().enumerate() {
+            let rate = speed.loops_per_real_second();
+            let remaining = (seconds - elapsed) * rate;
+            match self.speeds.get(index + 1) {
+                Some((end, _)) if ((end - start) as f64) < remaining => {
+                    elapsed += (end - start) as f64 / rate;
+                }
+                _ => return start + remaining.max(0.0).ceil() as usize,
+            }
+        }
+        0
+    }
+
+    fn real_seconds(&self, game_loop: usize) -> f64 {
+        self.speeds
+            .iter()
+            .enumerate()
+            .take_while(|(_, (start, _))| *start < game_loop)
+            .map(|(index, (start, speed))| {
+                let end = self
+                    .speeds
+                    .get(index + 1)
+                    .map_or(game_loop, |(end, _)| (*end).min(game_loop));
+                (end - start) as f64 / speed.loops_per_real_second()
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_protocol_version;
+    use crate::replay::build_replay;
+
+    #[test]
+    fn it_converts_game_loops_with_no_error() {
+        let protocol = load_protocol_version("93272");
+        let mut replay = build_replay("assets/replays/test.SC2Replay", &protocol);
+        let clock = replay.clock();
+        assert_eq!(clock, GameClock::default());
+        assert_eq!(replay.try_clock().unwrap(), clock);
+        let events = replay.game_events();
+        assert_eq!(
+            clock,
+            GameClock::from_events(GameSpeed::Faster, true, &events)
+        );
+
+        let time = clock.time(7155);
+        assert_eq!(time.to_string(), "05:19");
+        assert_eq!(time.game().as_secs(), 319);
+        assert_eq!(time.real_seconds, time.game_seconds);
+        assert_eq!(clock.game_loop_at_real_seconds(time.real_seconds), 7155);
+        assert_eq!(clock.game_loop_at_game_seconds(60.0), 1344);
+
+        let chat = &replay.message_events()[0];
+        assert_eq!(chat.time(&clock), clock.time(chat.game_loop));
+    }
+
+    #[test]
+    fn it_follows_game_speed_changes() {
+        let mut clock = GameClock::new(GameSpeed::Normal, false);
+        clock.speeds.push((160, GameSpeed::Faster));
+        assert_eq!(clock.speed_at(159), GameSpeed::Normal);
+        assert_eq!(clock.speed_at(160), GameSpeed::Faster);
+
+        let time = clock.time(384);
+        assert_eq!(time.game_seconds, 24.0);
+        assert_eq!(time.real_seconds, 20.0);
+        assert_eq!(time.to_string(), "00:24");
+        assert_eq!(clock.game_loop_at_real_seconds(20.0), 384);
+        assert_eq!(clock.game_loop_at_real_seconds(5.0), 80);
+        assert_eq!(clock.time(60 * 16 * 75).to_string(), "75:00");
+    }
+}
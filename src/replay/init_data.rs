@@ -0,0 +1,392 @@
+use super::decoders::DecodeError;
+use super::details::Details;
+use super::types::*;
+
+/// Typed content of `replay.initdata`, the lobby as synchronized when the game started.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InitData {
+    pub user_initial_data: Vec<UserInitialData>,
+    pub game_description: GameDescription,
+    pub lobby_state: LobbyState,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserInitialData {
+    pub name: String,
+    pub clan_tag: String,
+    pub clan_logo: Option<CacheHandle>,
+    pub highest_league: u8,
+    pub combined_race_levels: u32,
+    pub random_seed: u32,
+    pub race_preference: Option<u8>,
+    pub team_preference: Option<u8>,
+    pub observe: u8,
+    pub toon_handle: String,
+    pub scaled_rating: Option<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameOptions {
+    pub lock_teams: bool,
+    pub teams_together: bool,
+    pub advanced_shared_control: bool,
+    pub random_races: bool,
+    pub battle_net: bool,
+    pub amm: bool,
+    pub competitive: bool,
+    pub practice: bool,
+    pub cooperative: bool,
+    pub no_victory_or_defeat: bool,
+    pub hero_duplicates_allowed: bool,
+    pub fog: u8,
+    pub observers: u8,
+    pub user_difficulty: u8,
+    pub client_debug_flags: u64,
+    pub build_coach_enabled: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameDescription {
+    pub random_value: u32,
+    pub game_cache_name: String,
+    pub game_options: GameOptions,
+    pub game_speed: u8,
+    pub game_type: u8,
+    pub max_users: u8,
+    pub max_observers: u8,
+    pub max_players: u8,
+    pub max_teams: u8,
+    pub max_colors: u8,
+    pub max_races: u8,
+    pub max_controls: u8,
+    pub map_size_x: u16,
+    pub map_size_y: u16,
+    pub map_file_sync_checksum: u32,
+    pub map_file_name: String,
+    pub map_author_name: String,
+    pub mod_file_sync_checksum: u32,
+    pub default_difficulty: u8,
+    pub default_ai_build: u8,
+    pub cache_handles: Vec<CacheHandle>,
+    pub has_extension_mod: bool,
+    pub has_non_blizzard_extension_mod: bool,
+    pub is_blizzard_map: bool,
+    pub is_premade_ffa: bool,
+    pub is_coop_mode: bool,
+    pub is_realtime_mode: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LobbyState {
+    pub phase: u8,
+    pub max_users: u8,
+    pub max_observers: u8,
+    pub slots: Vec<LobbySlot>,
+    pub random_seed: u32,
+    pub host_user_id: Option<u8>,
+    pub is_single_player: bool,
+    pub picked_map_tag: u8,
+    pub game_duration: u32,
+    pub default_difficulty: u8,
+    pub default_ai_build: u8,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LobbySlot {
+    /// 0 open, 1 closed, 2 human, 3 computer.
+    pub control: u8,
+    pub user_id: Option<u8>,
+    pub team_id: u8,
+    pub color_pref: Option<u8>,
+    /// 0 Terran, 1 Zerg, 2 Protoss, `None` for random.
+    pub race_pref: Option<u8>,
+    /// Difficulty of a computer player, from 0 very easy to 9 cheater insane.
+    pub difficulty: u8,
+    pub ai_build: u8,
+    pub handicap: u32,
+    /// 0 participant, 1 spectator, 2 referee.
+    pub observe: u8,
+    pub logo_index: u32,
+    pub working_set_slot_id: Option<u8>,
+    pub toon_handle: String,
+    pub commander: String,
+}
+
+impl InitData {
+    /// Player id of `user_id`, the 1-based index of its entry in `details.players` matched
+    /// through their working set slot. `None` for observers.
+    pub fn player_id(&self, user_id: u8, details: &Details) -> Option<u8> {
+        let slot = self
+            .lobby_state
+            .slots
+            .iter()
+            .find(|slot| slot.user_id == Some(user_id))?;
+        slot.working_set_slot_id?;
+        details
+            .players
+            .iter()
+            .position(|player| player.working_set_slot_id == slot.working_set_slot_id)
+            .map(|index| index as u8 + 1)
+    }
+}
+
+impl LobbySlot {
+    pub fn is_computer(&self) -> bool {
+        self.control == 3
+    }
+
+    pub fn is_observer(&self) -> bool {
+        self.observe != 0
+    }
+
+    pub fn race_pref_name(&self) -> &'static str {
+        match self.race_pref {
+            Some(0) => "Terran",
+            Some(1) => "Zerg",
+            Some(2) => "Protoss",
+            _ => "Random",
+        }
+    }
+}
+
+fn optional_int(value: &ParsedFieldType, name: &str) -> Option<isize> {
+    value.field(name).and_then(ParsedFieldType::as_int)
+}
+
+/// Value of `m_race`, `m_team` or `m_color` wrapped in their single field preference struct.
+fn preference(value: &ParsedFieldType, name: &str, inner: &str) -> Option<u8> {
+    value
+        .field(name)
+        .and_then(|pref| optional_int(pref, inner))
+        .map(|pref| pref as u8)
+}
+
+impl From<&ParsedFieldType> for UserInitialData {
+    fn from(value: &ParsedFieldType) -> Self {
+        UserInitialData {
+            name: string(value, "m_name"),
+            clan_tag: string(value, "m_clanTag"),
+            clan_logo: value
+                .field("m_clanLogo")
+                .and_then(ParsedFieldType::as_blob)
+                .and_then(CacheHandle::from_bytes),
+            highest_league: int(value, "m_highestLeague") as u8,
+            combined_race_levels: int(value, "m_combinedRaceLevels") as u32,
+            random_seed: int(value, "m_randomSeed") as u32,
+            race_preference: preference(value, "m_racePreference", "m_race"),
+            team_preference: preference(value, "m_teamPreference", "m_team"),
+            observe: int(value, "m_observe") as u8,
+            toon_handle: string(value, "m_toonHandle"),
+            scaled_rating: optional_int(value, "m_scaledRating").map(|rating| rating as i32),
+        }
+    }
+}
+
+impl From<&ParsedFieldType> for GameOptions {
+    fn from(value: &ParsedFieldType) -> Self {
+        GameOptions {
+            lock_teams: boolean(value, "m_lockTeams"),
+            teams_together: boolean(value, "m_teamsTogether"),
+            advanced_shared_control: boolean(value, "m_advancedSharedControl"),
+            random_races: boolean(value, "m_randomRaces"),
+            battle_net: boolean(value, "m_battleNet"),
+            amm: boolean(value, "m_amm"),
+            competitive: boolean(value, "m_competitive"),
+            practice: boolean(value, "m_practice"),
+            cooperative: boolean(value, "m_cooperative"),
+            no_victory_or_defeat: boolean(value, "m_noVictoryOrDefeat"),
+            hero_duplicates_allowed: boolean(value, "m_heroDuplicatesAllowed"),
+            fog: int(value, "m_fog") as u8,
+            observers: int(value, "m_observers") as u8,
+            user_difficulty: int(value, "m_userDifficulty") as u8,
+            client_debug_flags: int(value, "m_clientDebugFlags") as u64,
+            build_coach_enabled: boolean(value, "m_buildCoachEnabled"),
+        }
+    }
+}
+
+impl TryFrom<&ParsedFieldType> for GameDescription {
+    type Error = DecodeError;
+
+    fn try_from(value: &ParsedFieldType) -> Result<Self, Self::Error> {
+        let cache_handles = value
+            .field("m_cacheHandles")
+            .and_then(ParsedFieldType::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|handle| handle.as_blob().and_then(CacheHandle::from_bytes))
+            .collect();
+
+        Ok(GameDescription {
+            random_value: int(value, "m_randomValue") as u32,
+            game_cache_name: string(value, "m_gameCacheName"),
+            game_options: value
+                .field("m_gameOptions")
+                .map(GameOptions::from)
+                .ok_or(DecodeError::MissingField("m_gameOptions"))?,
+            game_speed: int(value, "m_gameSpeed") as u8,
+            game_type: int(value, "m_gameType") as u8,
+            max_users: int(value, "m_maxUsers") as u8,
+            max_observers: int(value, "m_maxObservers") as u8,
+            max_players: int(value, "m_maxPlayers") as u8,
+            max_teams: int(value, "m_maxTeams") as u8,
+            max_colors: int(value, "m_maxColors") as u8,
+            max_races: int(value, "m_maxRaces") as u8,
+            max_controls: int(value, "m_maxControls") as u8,
+            map_size_x: int(value, "m_mapSizeX") as u16,
+            map_size_y: int(value, "m_mapSizeY") as u16,
+            map_file_sync_checksum: int(value, "m_mapFileSyncChecksum") as u32,
+            map_file_name: string(value, "m_mapFileName"),
+            map_author_name: string(value, "m_mapAuthorName"),
+            mod_file_sync_checksum: int(value, "m_modFileSyncChecksum") as u32,
+            default_difficulty: int(value, "m_defaultDifficulty") as u8,
+            default_ai_build: int(value, "m_defaultAIBuild") as u8,
+            cache_handles,
+            has_extension_mod: boolean(value, "m_hasExtensionMod"),
+            has_non_blizzard_extension_mod: boolean(value, "m_hasNonBlizzardExtensionMod"),
+            is_blizzard_map: boolean(value, "m_isBlizzardMap"),
+            is_premade_ffa: boolean(value, "m_isPremadeFFA"),
+            is_coop_mode: boolean(value, "m_isCoopMode"),
+            is_realtime_mode: boolean(value, "m_isRealtimeMode"),
+        })
+    }
+}
+
+impl From<&ParsedFieldType> for LobbySlot {
+    fn from(value: &ParsedFieldType) -> Self {
+        LobbySlot {
+            control: int(value, "m_control") as u8,
+            user_id: optional_int(value, "m_userId").map(|id| id as u8),
+            team_id: int(value, "m_teamId") as u8,
+            color_pref: preference(value, "m_colorPref", "m_color"),
+            race_pref: preference(value, "m_racePref", "m_race"),
+            difficulty: int(value, "m_difficulty") as u8,
+            ai_build: int(value, "m_aiBuild") as u8,
+            handicap: int(value, "m_handicap") as u32,
+            observe: int(value, "m_observe") as u8,
+            logo_index: int(value, "m_logoIndex") as u32,
+            working_set_slot_id: optional_int(value, "m_workingSetSlotId").map(|id| id as u8),
+            toon_handle: string(value, "m_toonHandle"),
+            commander: string(value, "m_commander"),
+        }
+    }
+}
+
+impl From<&ParsedFieldType> for LobbyState {
+    fn from(value: &ParsedFieldType) -> Self {
+        let slots = value
+            .field("m_slots")
+            .and_then(ParsedFieldType::as_array)
+            .unwrap_or_default()
+            .iter()
+            .map(LobbySlot::from)
+            .collect();
+
+        LobbyState {
+            phase: int(value, "m_phase") as u8,
+            max_users: int(value, "m_maxUsers") as u8,
+            max_observers: int(value, "m_maxObservers") as u8,
+            slots,
+            random_seed: int(value, "m_randomSeed") as u32,
+            host_user_id: optional_int(value, "m_hostUserId").map(|id| id as u8),
+            is_single_player: boolean(value, "m_isSinglePlayer"),
+            picked_map_tag: int(value, "m_pickedMapTag") as u8,
+            game_duration: int(value, "m_gameDuration") as u32,
+            default_difficulty: int(value, "m_defaultDifficulty") as u8,
+            default_ai_build: int(value, "m_defaultAIBuild") as u8,
+        }
+    }
+}
+
+impl TryFrom<&ParsedFieldType> for InitData {
+    type Error = DecodeError;
+
+    fn try_from(value: &ParsedFieldType) -> Result<Self, Self::Error> {
+        let lobby = value
+            .field("m_syncLobbyState")
+            .ok_or(DecodeError::MissingField("m_syncLobbyState"))?;
+        let user_initial_data = lobby
+            .field("m_userInitialData")
+            .and_then(ParsedFieldType::as_array)
+            .unwrap_or_default()
+            .iter()
+            .map(UserInitialData::from)
+            .collect();
+
+        Ok(InitData {
+            user_initial_data,
+            game_description: lobby
+                .field("m_gameDescription")
+                .ok_or(DecodeError::MissingField("m_gameDescription"))
+                .and_then(GameDescription::try_from)?,
+            lobby_state: lobby
+                .field("m_lobbyState")
+                .map(LobbyState::from)
+                .ok_or(DecodeError::MissingField("m_lobbyState"))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_protocol_version;
+    use crate::replay::build_replay;
+
+    #[test]
+    fn it_decodes_init_data_with_no_error() {
+        let protocol = load_protocol_version("93272");
+        let mut replay = build_replay("assets/replays/test.SC2Replay", &protocol);
+        let init_data = replay.init_data();
+
+        let description = &init_data.game_description;
+        assert_eq!((description.map_size_x, description.map_size_y), (200, 176));
+        assert_eq!(description.game_speed, 4);
+        assert_eq!(description.max_observers, 14);
+        assert!(description.game_options.battle_net);
+        assert_eq!(description.cache_handles.len(), 9);
+        assert_eq!(description.cache_handles[0].extension, "s2ma");
+
+        assert_eq!(init_data.user_initial_data.len(), 16);
+        assert_eq!(init_data.user_initial_data[1].name, "GLIDE");
+        assert_eq!(
+            init_data.user_initial_data[1]
+                .clan_logo
+                .as_ref()
+                .map(|logo| logo.extension.as_str()),
+            Some("clfl")
+        );
+
+        let slot = &init_data.lobby_state.slots[0];
+        assert_eq!(slot.user_id, Some(3));
+        assert_eq!(slot.team_id, 1);
+        assert_eq!(slot.race_pref_name(), "Terran");
+        assert_eq!(slot.difficulty, 3);
+        assert_eq!(slot.handicap, 100);
+        assert_eq!(slot.toon_handle, "2-S2-1-3885137");
+        assert!(!slot.is_observer() && !slot.is_computer());
+        assert_eq!(init_data.lobby_state.slots[1].race_pref_name(), "Protoss");
+        assert!(init_data.lobby_state.slots[2].is_observer());
+
+        let details = replay.details();
+        assert_eq!(init_data.player_id(3, &details), Some(1));
+        assert_eq!(init_data.player_id(6, &details), Some(2));
+        assert_eq!(init_data.player_id(0, &details), None);
+    }
+
+    #[test]
+    fn it_reports_missing_lobby_fields() {
+        let lobby = ParsedFieldType::Struct(vec![ParsedField {
+            name: String::from("m_syncLobbyState"),
+            value: Some(ParsedFieldType::Struct(Vec::new())),
+        }]);
+        assert_eq!(
+            InitData::try_from(&ParsedFieldType::Struct(Vec::new())),
+            Err(DecodeError::MissingField("m_syncLobbyState"))
+        );
+        assert_eq!(
+            InitData::try_from(&lobby),
+            Err(DecodeError::MissingField("m_gameDescription"))
+        );
+    }
+}
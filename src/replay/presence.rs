@@ -0,0 +1,209 @@
+use super::details::{Details, GameResult};
+use super::game_metadata::GameMetadata;
+use super::game_time::Timed;
+use super::init_data::InitData;
+use super::types::*;
+
+/// Game events of users joining and leaving the game.
+pub const PRESENCE_EVENTS: [&str; 2] = [
+    "NNet.Game.SGameUserJoinEvent",
+    "NNet.Game.SGameUserLeaveEvent",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PresenceChange {
+    /// A user joined an ongoing game, e.g. an observer or a player reconnecting.
+    Join {
+        name: String,
+        toon_handle: String,
+        /// 0 participant, 1 spectator, 2 referee.
+        observe: u8,
+    },
+    Leave {
+        /// Raw `m_leaveReason`, 0 when the user left on their own.
+        reason: u8,
+    },
+}
+
+/// User joining or leaving the game, from `SGameUserJoinEvent` and `SGameUserLeaveEvent`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PresenceEvent {
+    pub game_loop: usize,
+    pub user_id: u8,
+    pub change: PresenceChange,
+}
+
+impl Timed for PresenceEvent {
+    fn game_loop(&self) -> usize {
+        self.game_loop
+    }
+}
+
+impl PresenceEvent {
+    /// Decodes a join or leave event, `None` for any other event.
+    pub fn from_event(event: &Event) -> Option<Self> {
+        let data = event.data.value.as_ref()?;
+        let change = match event.name.as_str() {
+            "NNet.Game.SGameUserJoinEvent" => PresenceChange::Join {
+                name: string(data, "m_name"),
+                toon_handle: string(data, "m_toonHandle"),
+                observe: int(data, "m_observe") as u8,
+            },
+            "NNet.Game.SGameUserLeaveEvent" => PresenceChange::Leave {
+                reason: int(data, "m_leaveReason") as u8,
+            },
+            _ => return None,
+        };
+        Some(PresenceEvent {
+            game_loop: event.game_loop,
+            user_id: event.user_id? as u8,
+            change,
+        })
+    }
+
+    pub fn is_leave(&self) -> bool {
+        matches!(self.change, PresenceChange::Leave { .. })
+    }
+}
+
+/// Joins and leaves of the game events in game loop order.
+pub fn presence_timeline(events: &[Event]) -> Vec<PresenceEvent> {
+    events
+        .iter()
+        .filter_map(PresenceEvent::from_event)
+        .collect()
+}
+
+/// Where the result of `winner` comes from, in the order they are tried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WinnerSource {
+    Details,
+    Metadata,
+    /// The team whose last player left last, for replays without a recorded result.
+    LeaveOrder,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Winner {
+    pub player_ids: Vec<u8>,
+    pub source: WinnerSource,
+}
+
+/// Winning players from the results of the details, then of the metadata, then from the
+/// order in which the teams left. `None` when no team outlasted the others.
+pub fn winner(
+    details: &Details,
+    metadata: Option<&GameMetadata>,
+    init_data: &InitData,
+    timeline: &[PresenceEvent],
+) -> Option<Winner> {
+    let winners: Vec<u8> = details
+        .players
+        .iter()
+        .enumerate()
+        .filter(|(_, player)| player.result == GameResult::Victory)
+        .map(|(index, _)| index as u8 + 1)
+        .collect();
+    if !winners.is_empty() {
+        return Some(Winner {
+            player_ids: winners,
+            source: WinnerSource::Details,
+        });
+    }
+
+    let winners: Vec<u8> = metadata
+        .map(|metadata| {
+            metadata
+                .players
+                .iter()
+                .filter(|player| player.is_winner())
+                .map(|player| player.player_id)
+                .collect()
+        })
+        .unwrap_or_default();
+    if !winners.is_empty() {
+        return Some(Winner {
+            player_ids: winners,
+            source: WinnerSource::Metadata,
+        });
+    }
+
+    // Players who never left stay until the end of the replay.
+    let left_at = |user_id: u8| {
+        timeline
+            .iter()
+            .filter(|event| event.user_id == user_id && event.is_leave())
+            .map(|event| event.game_loop)
+            .next_back()
+            .unwrap_or(usize::MAX)
+    };
+    let mut teams: Vec<(u8, usize, Vec<u8>)> = Vec::new();
+    for slot in &init_data.lobby_state.slots {
+        let Some(user_id) = slot.user_id.filter(|_| !slot.is_observer()) else {
+            continue;
+        };
+        let Some(player_id) = init_data.player_id(user_id, details) else {
+            continue;
+        };
+        let left = left_at(user_id);
+        match teams
+            .iter_mut()
+            .find(|(team_id, ..)| *team_id == slot.team_id)
+        {
+            Some((_, last_left, players)) => {
+                *last_left = (*last_left).max(left);
+                players.push(player_id);
+            }
+            None => teams.push((slot.team_id, left, vec![player_id])),
+        }
+    }
+    teams.sort_by_key(|(_, last_left, _)| std::cmp::Reverse(*last_left));
+    match teams.as_slice() {
+        [(_, first, players), (_, second, _), ..] if first > second => Some(Winner {
+            player_ids: players.clone(),
+            source: WinnerSource::LeaveOrder,
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_protocol_version;
+    use crate::replay::build_replay;
+
+    #[test]
+    fn it_infers_winner_with_no_error() {
+        let protocol = load_protocol_version("93272");
+        let mut replay = build_replay("assets/replays/test.SC2Replay", &protocol);
+        let timeline = replay.presence_timeline();
+        assert_eq!(timeline.iter().filter(|event| event.is_leave()).count(), 8);
+        assert_eq!(timeline[0].game_loop, 7161);
+        assert_eq!(timeline[0].user_id, 6);
+        assert_eq!(timeline[0].change, PresenceChange::Leave { reason: 0 });
+
+        let winner = replay.winner().unwrap();
+        assert_eq!(winner.player_ids, [1]);
+        assert_eq!(winner.source, WinnerSource::Details);
+
+        let mut details = replay.details();
+        for player in &mut details.players {
+            player.result = GameResult::Undecided;
+        }
+        let metadata = replay.game_metadata().unwrap();
+        let init_data = replay.init_data();
+        let winner = super::winner(&details, metadata.as_ref(), &init_data, &timeline).unwrap();
+        assert_eq!(
+            (winner.player_ids, winner.source),
+            (vec![1], WinnerSource::Metadata)
+        );
+
+        let winner = super::winner(&details, None, &init_data, &timeline).unwrap();
+        assert_eq!(
+            (winner.player_ids, winner.source),
+            (vec![1], WinnerSource::LeaveOrder)
+        );
+        assert_eq!(super::winner(&details, None, &init_data, &[]), None);
+    }
+}
@@ -0,0 +1,452 @@
+use super::types::*;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+/// Index of the active selection among the control group ids of selection events, after
+/// the 10 control groups.
+pub const ACTIVE_SELECTION: usize = 10;
+pub const CONTROL_GROUP_COUNT: usize = 10;
+
+/// Update applied to a control group by `SControlGroupUpdateEvent`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlGroupUpdate {
+    Set,
+    Append,
+    Recall,
+    Clear,
+    SetAndSteal,
+    AppendAndSteal,
+    Unknown(u8),
+}
+
+impl From<isize> for ControlGroupUpdate {
+    fn from(value: isize) -> Self {
+        match value {
+            0 => ControlGroupUpdate::Set,
+            1 => ControlGroupUpdate::Append,
+            2 => ControlGroupUpdate::Recall,
+            3 => ControlGroupUpdate::Clear,
+            4 => ControlGroupUpdate::SetAndSteal,
+            5 => ControlGroupUpdate::AppendAndSteal,
+            other => ControlGroupUpdate::Unknown(other as u8),
+        }
+    }
+}
+
+/// How often a control group was set, appended to and recalled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ControlGroupUsage {
+    pub sets: usize,
+    pub appends: usize,
+    pub recalls: usize,
+    pub clears: usize,
+}
+
+impl ControlGroupUsage {
+    pub fn is_used(&self) -> bool {
+        self.sets + self.appends > 0
+    }
+}
+
+/// Position of a unit in the subgroup order of the game, from the `m_addSubgroups` of the
+/// selection delta which selected it: subgroups of higher priority first, subgroups of the
+/// same priority by unit type, then units of higher intra-subgroup priority first.
+type SubgroupOrder = (Reverse<isize>, isize, Reverse<isize>);
+
+/// Selection and control groups of a user.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct UserSelection {
+    /// Units of the 10 control groups followed by the active selection, in the subgroup order
+    /// of the game which the masks of the events index.
+    groups: [Vec<UnitTag>; CONTROL_GROUP_COUNT + 1],
+    /// Subgroup order of every unit selected so far.
+    orders: HashMap<UnitTag, SubgroupOrder>,
+    pub usage: [ControlGroupUsage; CONTROL_GROUP_COUNT],
+}
+
+impl UserSelection {
+    pub fn selection(&self) -> &[UnitTag] {
+        &self.groups[ACTIVE_SELECTION]
+    }
+
+    /// Units of the control group bound to the `index` hotkey, `None` past the 10 groups.
+    pub fn control_group(&self, index: usize) -> Option<&[UnitTag]> {
+        (index < CONTROL_GROUP_COUNT).then(|| self.groups[index].as_slice())
+    }
+
+    /// Number of control groups which were set at least once.
+    pub fn control_groups_used(&self) -> usize {
+        self.usage.iter().filter(|usage| usage.is_used()).count()
+    }
+
+    pub fn recalls(&self) -> usize {
+        self.usage.iter().map(|usage| usage.recalls).sum()
+    }
+
+    /// Sorts `group` in the subgroup order of the game, then by tag.
+    fn sort(orders: &HashMap<UnitTag, SubgroupOrder>, group: &mut [UnitTag]) {
+        group.sort_by_key(|tag| (orders.get(tag).copied(), *tag));
+    }
+
+    fn apply_delta(&mut self, index: usize, delta: &ParsedFieldType) {
+        let Some(group) = self.groups.get_mut(index) else {
+            return;
+        };
+        apply_mask(group, delta.field("m_removeMask"));
+
+        // The added tags are listed subgroup by subgroup, `m_count` tags each.
+        let subgroups = delta
+            .field("m_addSubgroups")
+            .and_then(ParsedFieldType::as_array)
+            .unwrap_or_default();
+        let mut orders = subgroups.iter().flat_map(|subgroup| {
+            let order = (
+                Reverse(int(subgroup, "m_subgroupPriority")),
+                int(subgroup, "m_unitLink"),
+                Reverse(int(subgroup, "m_intraSubgroupPriority")),
+            );
+            std::iter::repeat_n(order, int(subgroup, "m_count").max(0) as usize)
+        });
+        let added = delta
+            .field("m_addUnitTags")
+            .and_then(ParsedFieldType::as_array)
+            .unwrap_or_default();
+        let tags = added
+            .iter()
+            .filter_map(ParsedFieldType::as_int)
+            .map(|tag| UnitTag(tag as u32));
+        // The game lists as many tags as the subgroups count. Should a delta list more, the
+        // extra tags keep the order of an earlier selection, or sort first when they have none.
+        for tag in tags {
+            if let Some(order) = orders.next() {
+                self.orders.insert(tag, order);
+            }
+            if !group.contains(&tag) {
+                group.push(tag);
+            }
+        }
+        Self::sort(&self.orders, group);
+    }
+
+    fn apply_update(&mut self, index: usize, update: ControlGroupUpdate, mask: &ParsedFieldType) {
+        if index >= CONTROL_GROUP_COUNT {
+            return;
+        }
+        let usage = &mut self.usage[index];
+        match update {
+            ControlGroupUpdate::Set | ControlGroupUpdate::SetAndSteal => usage.sets += 1,
+            ControlGroupUpdate::Append | ControlGroupUpdate::AppendAndSteal => usage.appends += 1,
+            ControlGroupUpdate::Recall => usage.recalls += 1,
+            ControlGroupUpdate::Clear => usage.clears += 1,
+            ControlGroupUpdate::Unknown(_) => {}
+        }
+
+        let selection = self.groups[ACTIVE_SELECTION].clone();
+        if matches!(
+            update,
+            ControlGroupUpdate::SetAndSteal | ControlGroupUpdate::AppendAndSteal
+        ) {
+            for group in &mut self.groups[..CONTROL_GROUP_COUNT] {
+                group.retain(|tag| !selection.contains(tag));
+            }
+        }
+        let group = &mut self.groups[index];
+        match update {
+            ControlGroupUpdate::Set | ControlGroupUpdate::SetAndSteal => {
+                *group = selection;
+            }
+            ControlGroupUpdate::Append | ControlGroupUpdate::AppendAndSteal => {
+                let added: Vec<UnitTag> = selection
+                    .into_iter()
+                    .filter(|tag| !group.contains(tag))
+                    .collect();
+                group.extend(added);
+                Self::sort(&self.orders, group);
+            }
+            ControlGroupUpdate::Recall => {
+                // Units may have been selected again with another order since the group was
+                // set, e.g. after morphing.
+                Self::sort(&self.orders, group);
+                apply_mask(group, Some(mask));
+                self.groups[ACTIVE_SELECTION] = self.groups[index].clone();
+            }
+            ControlGroupUpdate::Clear => group.clear(),
+            ControlGroupUpdate::Unknown(_) => {}
+        }
+    }
+}
+
+/// Removes the units of `group` designated by a `m_removeMask` or `m_mask` choice.
+fn apply_mask(group: &mut Vec<UnitTag>, mask: Option<&ParsedFieldType>) {
+    let Some((variant, value)) = mask.and_then(ParsedFieldType::as_choice) else {
+        return;
+    };
+    let indices = || {
+        value
+            .as_array()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(ParsedFieldType::as_int)
+            .map(|index| index as usize)
+            .collect::<Vec<_>>()
+    };
+    let remove: Vec<bool> = match (variant, value) {
+        ("Mask", ParsedFieldType::BitArray { length, data }) => (0..group.len())
+            .map(|index| bit_array_bit(*length, data, index))
+            .collect(),
+        ("OneIndices", _) => {
+            let indices = indices();
+            (0..group.len())
+                .map(|index| indices.contains(&index))
+                .collect()
+        }
+        ("ZeroIndices", _) => {
+            let indices = indices();
+            (0..group.len())
+                .map(|index| !indices.contains(&index))
+                .collect()
+        }
+        _ => return,
+    };
+    let mut flags = remove.into_iter();
+    group.retain(|_| !flags.next().unwrap_or_default());
+}
+
+/// Bit `index` of a bit array, counting from its least significant bit as s2protocol reads
+/// the whole array as a single integer.
+fn bit_array_bit(length: usize, data: &[u8], index: usize) -> bool {
+    if index >= length {
+        return false;
+    }
+    // Position of the bit from the start of the stream, which holds the most significant bit.
+    let position = length - 1 - index;
+    let full_bytes = length / 8;
+    let (byte, width, offset) = if position < full_bytes * 8 {
+        (data[position / 8], 8, position % 8)
+    } else {
+        (data[full_bytes], length % 8, position - full_bytes * 8)
+    };
+    (byte >> (width - 1 - offset)) & 1 == 1
+}
+
+/// Replays selection deltas and control group updates to rebuild the selection and control
+/// groups of every user.
+///
+/// Dead units need no tracking, as the game removes them from the selection with a delta and
+/// from control groups with the mask of their next recall. Masks address units by their index
+/// in the group, so groups are kept in the subgroup order of the game.
+#[derive(Debug, Default)]
+pub struct SelectionTracker {
+    users: HashMap<u8, UserSelection>,
+}
+
+impl SelectionTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// State after the game events up to and including `game_loop`.
+    pub fn until(events: &[Event], game_loop: usize) -> Self {
+        let mut tracker = Self::new();
+        for event in events
+            .iter()
+            .take_while(|event| event.game_loop <= game_loop)
+        {
+            tracker.process(event);
+        }
+        tracker
+    }
+
+    pub fn user(&self, user_id: u8) -> Option<&UserSelection> {
+        self.users.get(&user_id)
+    }
+
+    pub fn users(&self) -> &HashMap<u8, UserSelection> {
+        &self.users
+    }
+
+    /// Updates the selections with a selection delta or control group event, ignoring any
+    /// other event.
+    pub fn process(&mut self, event: &Event) {
+        let (Some(user_id), Some(data)) = (event.user_id, event.data.value.as_ref()) else {
+            return;
+        };
+        let Ok(user_id) = u8::try_from(user_id) else {
+            return;
+        };
+        let user = self.users.entry(user_id).or_default();
+        match event.name.as_str() {
+            "NNet.Game.SSelectionDeltaEvent" => {
+                let index = usize::try_from(int(data, "m_controlGroupId"));
+                if let (Some(delta), Ok(index)) = (data.field("m_delta"), index) {
+                    user.apply_delta(index, delta);
+                }
+            }
+            "NNet.Game.SControlGroupUpdateEvent" => {
+                let index = usize::try_from(int(data, "m_controlGroupIndex"));
+                if let (Some(mask), Ok(index)) = (data.field("m_mask"), index) {
+                    let update = ControlGroupUpdate::from(int(data, "m_controlGroupUpdate"));
+                    user.apply_update(index, update, mask);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_protocol_version;
+    use crate::replay::build_replay;
+    use crate::replay::types::fixtures::{event, fields};
+
+    #[test]
+    fn it_tracks_selections_with_no_error() {
+        let protocol = load_protocol_version("93272");
+        let mut replay = build_replay("assets/replays/test.SC2Replay", &protocol);
+
+        let tracker = replay.selection_at(409);
+        let user = tracker.user(6).unwrap();
+        assert!(!user.selection().is_empty());
+        assert_eq!(user.control_group(0), Some(user.selection()));
+        assert_eq!(user.control_group(10), None);
+
+        let tracker = replay.selection_at(usize::MAX);
+        let user = tracker.user(6).unwrap();
+        assert_eq!(user.control_groups_used(), 7);
+        assert_eq!(
+            user.usage[1],
+            ControlGroupUsage {
+                sets: 25,
+                appends: 2,
+                recalls: 212,
+                clears: 1,
+            }
+        );
+        assert_eq!(tracker.user(3).unwrap().recalls(), 559);
+        assert!(tracker.user(0).unwrap().control_groups_used() == 0);
+    }
+
+    /// Delta of the active selection adding `tags` from subgroups of unit link, priority and
+    /// count.
+    fn selection_delta(
+        remove_mask: ParsedFieldType,
+        subgroups: &[(isize, isize, isize)],
+        tags: &[isize],
+    ) -> Event {
+        let subgroups = subgroups
+            .iter()
+            .map(|&(unit_link, priority, count)| {
+                fields(vec![
+                    ("m_unitLink", ParsedFieldType::Int(unit_link)),
+                    ("m_subgroupPriority", ParsedFieldType::Int(priority)),
+                    ("m_intraSubgroupPriority", ParsedFieldType::Int(0)),
+                    ("m_count", ParsedFieldType::Int(count)),
+                ])
+            })
+            .collect();
+        let tags = tags.iter().copied().map(ParsedFieldType::Int).collect();
+        event(
+            "NNet.Game.SSelectionDeltaEvent",
+            100,
+            Some(3),
+            vec![
+                (
+                    "m_controlGroupId",
+                    ParsedFieldType::Int(ACTIVE_SELECTION as isize),
+                ),
+                (
+                    "m_delta",
+                    fields(vec![
+                        ("m_subgroupIndex", ParsedFieldType::Int(0)),
+                        ("m_removeMask", remove_mask),
+                        ("m_addSubgroups", ParsedFieldType::Array(subgroups)),
+                        ("m_addUnitTags", ParsedFieldType::Array(tags)),
+                    ]),
+                ),
+            ],
+        )
+    }
+
+    fn control_group_update(update: isize, mask: ParsedFieldType) -> Event {
+        event(
+            "NNet.Game.SControlGroupUpdateEvent",
+            100,
+            Some(3),
+            vec![
+                ("m_controlGroupIndex", ParsedFieldType::Int(1)),
+                ("m_controlGroupUpdate", ParsedFieldType::Int(update)),
+                ("m_mask", mask),
+            ],
+        )
+    }
+
+    #[test]
+    fn it_removes_masked_units_in_subgroup_order() {
+        let none = || fields(vec![("None", ParsedFieldType::Null)]);
+        let tags = |tags: &[u32]| tags.iter().map(|&tag| UnitTag(tag)).collect::<Vec<_>>();
+        let mut tracker = SelectionTracker::new();
+
+        // Two units of a low priority subgroup are listed before two of a higher priority one.
+        tracker.process(&selection_delta(
+            none(),
+            &[(110, 10, 2), (220, 20, 2)],
+            &[1, 2, 3, 4],
+        ));
+        let user = tracker.user(3).unwrap();
+        assert_eq!(user.selection(), tags(&[3, 4, 1, 2]));
+
+        tracker.process(&control_group_update(0, none()));
+        let one_indices = fields(vec![(
+            "OneIndices",
+            ParsedFieldType::Array(vec![ParsedFieldType::Int(0)]),
+        )]);
+        tracker.process(&selection_delta(one_indices, &[], &[]));
+        let user = tracker.user(3).unwrap();
+        assert_eq!(user.selection(), tags(&[4, 1, 2]));
+
+        // Recalling the group removes its third unit, counting from the least significant bit.
+        let mask = fields(vec![(
+            "Mask",
+            ParsedFieldType::BitArray {
+                length: 4,
+                data: vec![0b0100],
+            },
+        )]);
+        tracker.process(&control_group_update(2, mask));
+        let user = tracker.user(3).unwrap();
+        assert_eq!(user.control_group(1), Some(tags(&[3, 4, 2]).as_slice()));
+        assert_eq!(user.selection(), tags(&[3, 4, 2]));
+    }
+
+    #[test]
+    fn it_skips_events_with_out_of_range_ids() {
+        let none = || fields(vec![("None", ParsedFieldType::Null)]);
+        let mut tracker = SelectionTracker::new();
+        let mut delta = selection_delta(none(), &[(110, 10, 1)], &[1]);
+        delta.user_id = Some(259);
+        tracker.process(&delta);
+        assert!(tracker.user(3).is_none());
+
+        tracker.process(&event(
+            "NNet.Game.SControlGroupUpdateEvent",
+            100,
+            Some(3),
+            vec![
+                ("m_controlGroupIndex", ParsedFieldType::Int(-1)),
+                ("m_controlGroupUpdate", ParsedFieldType::Int(0)),
+                ("m_mask", none()),
+            ],
+        ));
+        assert_eq!(tracker.user(3).unwrap().control_groups_used(), 0);
+    }
+
+    #[test]
+    fn it_reads_bit_array_masks_from_the_least_significant_bit() {
+        assert!(bit_array_bit(2, &[2], 1) && !bit_array_bit(2, &[2], 0));
+        assert!(bit_array_bit(9, &[0x80, 0], 8));
+        assert!(bit_array_bit(9, &[0, 1], 0));
+        assert!(!bit_array_bit(9, &[0, 1], 9));
+    }
+}
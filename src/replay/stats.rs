@@ -0,0 +1,193 @@
+use super::game_time::Timed;
+use super::types::*;
+use std::io::{self, Write};
+
+/// Fixed point scale of the food values of `SPlayerStatsEvent`.
+const FOOD_SCALE: f64 = 4096.0;
+
+/// Declares `PlayerStats` along with its decoding and CSV columns from a single list of
+/// `m_scoreValue*` fields.
+macro_rules! player_stats {
+    ($($field:ident => $name:literal,)*) => {
+        /// Snapshot of the economy of a player, sent by `SPlayerStatsEvent` every 10 game seconds.
+        #[derive(Debug, Clone, PartialEq, Eq)]
+        pub struct PlayerStats {
+            pub game_loop: usize,
+            pub player_id: u8,
+            $(pub $field: i32,)*
+        }
+
+        impl PlayerStats {
+            /// Names of the columns written by `write_csv`.
+            pub const CSV_HEADER: &'static [&'static str] =
+                &["game_loop", "player_id", $(stringify!($field),)*];
+
+            fn from_stats(game_loop: usize, player_id: u8, stats: &ParsedFieldType) -> Self {
+                PlayerStats {
+                    game_loop,
+                    player_id,
+                    $($field: int(stats, $name) as i32,)*
+                }
+            }
+
+            fn csv_values(&self) -> Vec<String> {
+                vec![
+                    self.game_loop.to_string(),
+                    self.player_id.to_string(),
+                    $(self.$field.to_string(),)*
+                ]
+            }
+        }
+    };
+}
+
+player_stats! {
+    minerals_current => "m_scoreValueMineralsCurrent",
+    vespene_current => "m_scoreValueVespeneCurrent",
+    minerals_collection_rate => "m_scoreValueMineralsCollectionRate",
+    vespene_collection_rate => "m_scoreValueVespeneCollectionRate",
+    workers_active_count => "m_scoreValueWorkersActiveCount",
+    minerals_used_in_progress_army => "m_scoreValueMineralsUsedInProgressArmy",
+    minerals_used_in_progress_economy => "m_scoreValueMineralsUsedInProgressEconomy",
+    minerals_used_in_progress_technology => "m_scoreValueMineralsUsedInProgressTechnology",
+    vespene_used_in_progress_army => "m_scoreValueVespeneUsedInProgressArmy",
+    vespene_used_in_progress_economy => "m_scoreValueVespeneUsedInProgressEconomy",
+    vespene_used_in_progress_technology => "m_scoreValueVespeneUsedInProgressTechnology",
+    minerals_used_current_army => "m_scoreValueMineralsUsedCurrentArmy",
+    minerals_used_current_economy => "m_scoreValueMineralsUsedCurrentEconomy",
+    minerals_used_current_technology => "m_scoreValueMineralsUsedCurrentTechnology",
+    vespene_used_current_army => "m_scoreValueVespeneUsedCurrentArmy",
+    vespene_used_current_economy => "m_scoreValueVespeneUsedCurrentEconomy",
+    vespene_used_current_technology => "m_scoreValueVespeneUsedCurrentTechnology",
+    minerals_lost_army => "m_scoreValueMineralsLostArmy",
+    minerals_lost_economy => "m_scoreValueMineralsLostEconomy",
+    minerals_lost_technology => "m_scoreValueMineralsLostTechnology",
+    vespene_lost_army => "m_scoreValueVespeneLostArmy",
+    vespene_lost_economy => "m_scoreValueVespeneLostEconomy",
+    vespene_lost_technology => "m_scoreValueVespeneLostTechnology",
+    minerals_killed_army => "m_scoreValueMineralsKilledArmy",
+    minerals_killed_economy => "m_scoreValueMineralsKilledEconomy",
+    minerals_killed_technology => "m_scoreValueMineralsKilledTechnology",
+    vespene_killed_army => "m_scoreValueVespeneKilledArmy",
+    vespene_killed_economy => "m_scoreValueVespeneKilledEconomy",
+    vespene_killed_technology => "m_scoreValueVespeneKilledTechnology",
+    food_used => "m_scoreValueFoodUsed",
+    food_made => "m_scoreValueFoodMade",
+    minerals_used_active_forces => "m_scoreValueMineralsUsedActiveForces",
+    vespene_used_active_forces => "m_scoreValueVespeneUsedActiveForces",
+    minerals_friendly_fire_army => "m_scoreValueMineralsFriendlyFireArmy",
+    minerals_friendly_fire_economy => "m_scoreValueMineralsFriendlyFireEconomy",
+    minerals_friendly_fire_technology => "m_scoreValueMineralsFriendlyFireTechnology",
+    vespene_friendly_fire_army => "m_scoreValueVespeneFriendlyFireArmy",
+    vespene_friendly_fire_economy => "m_scoreValueVespeneFriendlyFireEconomy",
+    vespene_friendly_fire_technology => "m_scoreValueVespeneFriendlyFireTechnology",
+}
+
+impl Timed for PlayerStats {
+    fn game_loop(&self) -> usize {
+        self.game_loop
+    }
+}
+
+impl PlayerStats {
+    /// Decodes a `SPlayerStatsEvent`, `None` for any other event.
+    pub fn from_event(event: &Event) -> Option<Self> {
+        if event.name != "NNet.Replay.Tracker.SPlayerStatsEvent" {
+            return None;
+        }
+        let data = event.data.value.as_ref()?;
+        Some(PlayerStats::from_stats(
+            event.game_loop,
+            int(data, "m_playerId") as u8,
+            data.field("m_stats")?,
+        ))
+    }
+
+    /// Minerals and vespene spent on the army alive.
+    pub fn army_value(&self) -> i32 {
+        self.minerals_used_current_army + self.vespene_used_current_army
+    }
+
+    pub fn resources_lost(&self) -> i32 {
+        self.minerals_lost_army
+            + self.minerals_lost_economy
+            + self.minerals_lost_technology
+            + self.vespene_lost_army
+            + self.vespene_lost_economy
+            + self.vespene_lost_technology
+    }
+
+    pub fn resources_killed(&self) -> i32 {
+        self.minerals_killed_army
+            + self.minerals_killed_economy
+            + self.minerals_killed_technology
+            + self.vespene_killed_army
+            + self.vespene_killed_economy
+            + self.vespene_killed_technology
+    }
+
+    /// Supply used, fractional when an odd number of Zerglings is alive.
+    pub fn supply_used(&self) -> f64 {
+        self.food_used as f64 / FOOD_SCALE
+    }
+
+    pub fn supply_made(&self) -> f64 {
+        self.food_made as f64 / FOOD_SCALE
+    }
+}
+
+/// Stats of `player_id` in game loop order.
+pub fn stats_timeline(events: &[Event], player_id: u8) -> Vec<PlayerStats> {
+    events
+        .iter()
+        .filter_map(PlayerStats::from_event)
+        .filter(|stats| stats.player_id == player_id)
+        .collect()
+}
+
+/// Writes `stats` as CSV with a `PlayerStats::CSV_HEADER` header row.
+pub fn write_csv<W: Write>(stats: &[PlayerStats], mut writer: W) -> io::Result<()> {
+    writeln!(writer, "{}", PlayerStats::CSV_HEADER.join(","))?;
+    for row in stats {
+        writeln!(writer, "{}", row.csv_values().join(","))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_protocol_version;
+    use crate::replay::build_replay;
+
+    #[test]
+    fn it_builds_stats_timeline_with_no_error() {
+        let protocol = load_protocol_version("93272");
+        let mut replay = build_replay("assets/replays/test.SC2Replay", &protocol);
+        let timeline = replay.stats_timeline(1);
+        assert_eq!(timeline.len(), 46);
+
+        let first = &timeline[0];
+        assert_eq!((first.game_loop, first.player_id), (1, 1));
+        assert_eq!(first.workers_active_count, 12);
+        assert_eq!((first.supply_used(), first.supply_made()), (12.0, 15.0));
+
+        let last = timeline.last().unwrap();
+        assert_eq!(last.game_loop, 7161);
+        assert_eq!(last.minerals_collection_rate, 951);
+        assert_eq!(last.army_value(), 1225);
+        assert_eq!(last.resources_lost(), 879);
+        assert_eq!(last.resources_killed(), 2550);
+
+        let mut csv = Vec::new();
+        write_csv(&timeline, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("game_loop,player_id,minerals_current,"));
+        assert!(lines.next().unwrap().starts_with("1,1,50,0,0,0,12,"));
+        assert_eq!(lines.count(), 45);
+    }
+}
@@ -0,0 +1,96 @@
+use super::types::*;
+use std::iter::Peekable;
+use std::vec::IntoIter;
+
+/// Stream an event was decoded from. Events of the same game loop are ordered by source, in
+/// the order of the variants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EventSource {
+    Game,
+    Message,
+    Tracker,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TimelineEvent {
+    pub source: EventSource,
+    pub event: Event,
+}
+
+/// Game, message and tracker events merged into a single stream ordered by game loop.
+///
+/// Within a game loop, game events come first, then message events, then tracker events, and
+/// the events of a stream keep their order.
+pub struct Timeline {
+    streams: Vec<(EventSource, Peekable<IntoIter<Event>>)>,
+}
+
+impl Timeline {
+    pub fn new(game: Vec<Event>, message: Vec<Event>, tracker: Vec<Event>) -> Self {
+        Timeline {
+            streams: vec![
+                (EventSource::Game, game.into_iter().peekable()),
+                (EventSource::Message, message.into_iter().peekable()),
+                (EventSource::Tracker, tracker.into_iter().peekable()),
+            ],
+        }
+    }
+}
+
+impl Iterator for Timeline {
+    type Item = TimelineEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Streams are in source order, so the first stream with the earliest loop wins ties.
+        let (source, stream) = self
+            .streams
+            .iter_mut()
+            .filter_map(|(source, stream)| {
+                let game_loop = stream.peek()?.game_loop;
+                Some((game_loop, source, stream))
+            })
+            .min_by_key(|(game_loop, source, _)| (*game_loop, **source))
+            .map(|(_, source, stream)| (*source, stream))?;
+        Some(TimelineEvent {
+            source,
+            event: stream.next()?,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.streams.iter().map(|(_, stream)| stream.len()).sum();
+        (len, Some(len))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_protocol_version;
+    use crate::replay::build_replay;
+
+    #[test]
+    fn it_merges_event_streams_with_no_error() {
+        let protocol = load_protocol_version("93272");
+        let mut replay = build_replay("assets/replays/test.SC2Replay", &protocol);
+        let total = replay.game_events().len()
+            + replay.message_events().len()
+            + replay.tracker_events().len();
+        let timeline: Vec<TimelineEvent> = replay.timeline().collect();
+        assert_eq!(timeline.len(), total);
+        assert!(timeline.windows(2).all(|pair| {
+            (pair[0].event.game_loop, pair[0].source) <= (pair[1].event.game_loop, pair[1].source)
+        }));
+
+        let chat = timeline
+            .iter()
+            .position(|item| item.event.name == "NNet.Game.SChatMessage")
+            .unwrap();
+        assert_eq!(timeline[chat].source, EventSource::Message);
+        assert_eq!(timeline[chat].event.game_loop, 7155);
+        assert!(timeline[..chat]
+            .iter()
+            .any(|item| item.source == EventSource::Tracker
+                && item.event.name == "NNet.Replay.Tracker.SUnitDiedEvent"));
+    }
+}
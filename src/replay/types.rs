@@ -1,13 +1,21 @@
-// #[derive(Debug, PartialEq)]
-// pub struct Replay {
-//     pub header: Header,
-//     pub details: Details,
-//     pub init_data: InitData,
-//     pub game_events: Vec<GameEvent>,
-//     pub message_events: Vec<MessageEvent>,
-//     pub tracker_events: Vec<TrackerEvent>,
-// }
-//
+use super::decoders::DecodeError;
+use super::game_time::Timed;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::time::Duration;
+
+/// Game loops per second of game time.
+pub const GAME_LOOPS_PER_SECOND: f64 = 16.0;
+/// Game loops per real second at the "Faster" game speed ladder games are played at.
+pub const GAME_LOOPS_PER_REAL_SECOND: f64 = 22.4;
+
+/// Real time elapsed over `game_loops` at the "Faster" game speed.
+pub fn game_loops_to_duration(game_loops: u32) -> Duration {
+    Duration::from_secs_f64(game_loops as f64 / GAME_LOOPS_PER_REAL_SECOND)
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Version {
     pub flags: u8,
@@ -18,10 +26,61 @@ pub struct Version {
     pub base_build: u32,
 }
 
+/// Typed content of the MPQ user data header.
 #[derive(Debug, PartialEq)]
 pub struct UserData {
     pub signature: Option<Vec<u8>>,
     pub version: Version,
+    pub replay_type: u8,
+    pub elapsed_game_loops: u32,
+    pub use_scaled_time: bool,
+    pub ngdp_root_key: Vec<u8>,
+    pub data_build_num: u32,
+}
+
+impl UserData {
+    /// Real duration of the game, assuming the "Faster" game speed.
+    pub fn duration(&self) -> Duration {
+        game_loops_to_duration(self.elapsed_game_loops)
+    }
+}
+
+impl From<&ParsedFieldType> for Version {
+    fn from(value: &ParsedFieldType) -> Self {
+        Version {
+            flags: int(value, "m_flags") as u8,
+            major: int(value, "m_major") as u8,
+            minor: int(value, "m_minor") as u8,
+            revision: int(value, "m_revision") as u8,
+            build: int(value, "m_build") as u32,
+            base_build: int(value, "m_baseBuild") as u32,
+        }
+    }
+}
+
+impl From<&ParsedFieldType> for UserData {
+    fn from(value: &ParsedFieldType) -> Self {
+        UserData {
+            signature: value
+                .field("m_signature")
+                .and_then(ParsedFieldType::as_blob)
+                .map(<[u8]>::to_vec),
+            version: value
+                .field("m_version")
+                .map(Version::from)
+                .expect("Failed to find version in user data"),
+            replay_type: int(value, "m_type") as u8,
+            elapsed_game_loops: int(value, "m_elapsedGameLoops") as u32,
+            use_scaled_time: boolean(value, "m_useScaledTime"),
+            ngdp_root_key: value
+                .field("m_ngdpRootKey")
+                .and_then(|key| key.field("m_data"))
+                .and_then(ParsedFieldType::as_blob)
+                .map(<[u8]>::to_vec)
+                .unwrap_or_default(),
+            data_build_num: int(value, "m_dataBuildNum") as u32,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -30,21 +89,445 @@ pub struct ParsedField {
     pub value: Option<ParsedFieldType>,
 }
 
+impl ParsedField {
+    /// Looks up the value of the field `name` of a struct, `None` when it is missing or absent.
+    pub fn field(&self, name: &str) -> Option<&ParsedFieldType> {
+        self.value.as_ref()?.field(name)
+    }
+}
+
+/// Decoded value of a protocol type.
+///
+/// Choices have no variant of their own: they decode to a `Struct` with a single field named
+/// after the selected variant, e.g. `{"TargetPoint": {...}}` for the target of a command, as
+/// returned by `as_choice`.
 #[derive(Debug, PartialEq)]
 pub enum ParsedFieldType {
     Null,
     Bool(bool),
-    Blob(String),
+    /// Raw bytes of a blob. Blobs hold binary data such as hashes and signatures as well as
+    /// text, which `as_string` decodes lossily as UTF-8.
+    Blob(Vec<u8>),
     Int(isize),
     FourCC(Vec<u8>),
     Array(Vec<ParsedFieldType>),
     Struct(Vec<ParsedField>),
-    BitArray { length: usize, data: Vec<u8> },
+    BitArray {
+        length: usize,
+        data: Vec<u8>,
+    },
 }
 
-#[derive(Debug)]
+/// Entry of the `(listfile)` of a replay archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveFile {
+    pub name: String,
+    /// Uncompressed size in bytes.
+    pub size: u32,
+}
+
+/// A decoded event along with the metadata s2protocol attaches as `_event`, `_eventid`,
+/// `_gameloop`, `_userid` and `_bits`.
+#[derive(Debug, PartialEq)]
+pub struct Event {
+    pub name: String,
+    pub event_id: u16,
+    pub game_loop: usize,
+    pub user_id: Option<isize>,
+    pub bits: usize,
+    pub data: ParsedField,
+}
+
+impl Event {
+    /// Tag of the unit a tracker event is about, from its `m_unitTagIndex` and
+    /// `m_unitTagRecycle` fields.
+    pub fn unit_tag(&self) -> Option<UnitTag> {
+        UnitTag::from_fields(self.data.value.as_ref()?, "m_unitTag")
+    }
+
+    /// Player a tracker event is about, from its `m_playerId` field, or `m_controlPlayerId`
+    /// for unit events.
+    pub fn player_id(&self) -> Option<u8> {
+        player_id(&self.data)
+    }
+}
+
+fn player_id(data: &ParsedField) -> Option<u8> {
+    let player_id = data
+        .field("m_playerId")
+        .or_else(|| data.field("m_controlPlayerId"))?
+        .as_int()?;
+    u8::try_from(player_id).ok()
+}
+
+impl Timed for Event {
+    fn game_loop(&self) -> usize {
+        self.game_loop
+    }
+}
+
+/// Selects the events decoded from an event stream, the others being skipped without building
+/// their data. Empty criteria select every event.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EventFilter {
+    /// Full event names, e.g. `NNet.Replay.Tracker.SUnitDiedEvent`.
+    pub names: Vec<String>,
+    pub event_ids: Vec<u16>,
+    /// Users of the events. Events without a user, like tracker events, are never filtered out
+    /// by user.
+    pub user_ids: Vec<u8>,
+    /// Players of the events, as returned by `Event::player_id`. Events without a player are
+    /// filtered out. As the player is part of the event data, events are decoded before being
+    /// filtered out by player.
+    pub player_ids: Vec<u8>,
+    pub loop_range: Option<Range<usize>>,
+}
+
+impl EventFilter {
+    pub fn matches(
+        &self,
+        name: &str,
+        event_id: u16,
+        user_id: Option<isize>,
+        game_loop: usize,
+    ) -> bool {
+        (self.names.is_empty() || self.names.iter().any(|selected| selected == name))
+            && (self.event_ids.is_empty() || self.event_ids.contains(&event_id))
+            && user_id.is_none_or(|user_id| {
+                self.user_ids.is_empty()
+                    || u8::try_from(user_id).is_ok_and(|user_id| self.user_ids.contains(&user_id))
+            })
+            && self
+                .loop_range
+                .as_ref()
+                .is_none_or(|range| range.contains(&game_loop))
+    }
+
+    /// Whether the decoded `data` of an event matches the player criterion.
+    pub fn matches_player(&self, data: &ParsedField) -> bool {
+        self.player_ids.is_empty()
+            || player_id(data).is_some_and(|player_id| self.player_ids.contains(&player_id))
+    }
+
+    /// Whether no event from `game_loop` on can match, as streams are in game loop order.
+    pub fn is_past(&self, game_loop: usize) -> bool {
+        self.loop_range
+            .as_ref()
+            .is_some_and(|range| game_loop >= range.end)
+    }
+}
+
+impl ParsedFieldType {
+    /// Looks up the value of the field `name` of a struct, `None` when it is missing or absent.
+    pub fn field(&self, name: &str) -> Option<&ParsedFieldType> {
+        self.as_struct()?
+            .iter()
+            .find(|f| f.name == name)?
+            .value
+            .as_ref()
+    }
+
+    pub fn as_int(&self) -> Option<isize> {
+        match self {
+            ParsedFieldType::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            ParsedFieldType::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_blob(&self) -> Option<&[u8]> {
+        match self {
+            ParsedFieldType::Blob(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns a blob as text, replacing invalid UTF-8 sequences.
+    pub fn as_string(&self) -> Option<String> {
+        self.as_blob()
+            .map(|value| String::from_utf8_lossy(value).into_owned())
+    }
+
+    pub fn as_fourcc(&self) -> Option<String> {
+        match self {
+            ParsedFieldType::FourCC(value) => Some(
+                String::from_utf8_lossy(value)
+                    .trim_matches(char::from(0))
+                    .to_string(),
+            ),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[ParsedFieldType]> {
+        match self {
+            ParsedFieldType::Array(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Name and value of the selected variant of a choice.
+    pub fn as_choice(&self) -> Option<(&str, &ParsedFieldType)> {
+        match self.as_struct()? {
+            [field] => Some((field.name.as_str(), field.value.as_ref()?)),
+            _ => None,
+        }
+    }
+
+    pub fn as_struct(&self) -> Option<&[ParsedField]> {
+        match self {
+            ParsedFieldType::Struct(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// Integer value of the field `name` of a struct, 0 when it is missing.
+pub(crate) fn int(value: &ParsedFieldType, name: &str) -> isize {
+    value
+        .field(name)
+        .and_then(ParsedFieldType::as_int)
+        .unwrap_or_default()
+}
+
+/// Text of the blob field `name` of a struct, empty when it is missing.
+pub(crate) fn string(value: &ParsedFieldType, name: &str) -> String {
+    value
+        .field(name)
+        .and_then(ParsedFieldType::as_string)
+        .unwrap_or_default()
+}
+
+/// Boolean value of the field `name` of a struct, false when it is missing.
+pub(crate) fn boolean(value: &ParsedFieldType, name: &str) -> bool {
+    value
+        .field(name)
+        .and_then(ParsedFieldType::as_bool)
+        .unwrap_or_default()
+}
+
+/// Unit identifier as packed in game events, combining the index of the unit with a recycle
+/// counter which changes whenever the index is reused by a new unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct UnitTag(pub u32);
+
+impl UnitTag {
+    const INDEX_MASK: u32 = 0x0000_3fff;
+    const RECYCLE_MASK: u32 = 0x0003_ffff;
+
+    /// Tag of `index` and `recycle`, keeping only the 14 and 18 bits the tag stores of them.
+    pub fn new(index: u32, recycle: u32) -> Self {
+        UnitTag(((index & Self::INDEX_MASK) << 18) | (recycle & Self::RECYCLE_MASK))
+    }
+
+    /// Tag of `index` and `recycle`, `None` when either does not fit its bits of the tag.
+    pub fn try_new(index: u32, recycle: u32) -> Option<Self> {
+        (index <= Self::INDEX_MASK && recycle <= Self::RECYCLE_MASK)
+            .then(|| UnitTag::new(index, recycle))
+    }
+
+    pub fn index(&self) -> u32 {
+        (self.0 >> 18) & Self::INDEX_MASK
+    }
+
+    pub fn recycle(&self) -> u32 {
+        self.0 & Self::RECYCLE_MASK
+    }
+
+    /// Tag from the index and recycle fields of a tracker event sharing the prefix `name`,
+    /// e.g. `m_unitTag` for `m_unitTagIndex` and `m_unitTagRecycle`.
+    pub fn from_fields(value: &ParsedFieldType, name: &str) -> Option<Self> {
+        let index = value.field(&format!("{name}Index"))?.as_int()?;
+        let recycle = value.field(&format!("{name}Recycle"))?.as_int()?;
+        UnitTag::try_new(u32::try_from(index).ok()?, u32::try_from(recycle).ok()?)
+    }
+}
+
+impl From<u32> for UnitTag {
+    fn from(tag: u32) -> Self {
+        UnitTag(tag)
+    }
+}
+
+impl From<UnitTag> for u32 {
+    fn from(tag: UnitTag) -> Self {
+        tag.0
+    }
+}
+
+/// Reference to a file on Blizzard's depot, such as a map or a mod, stored as a 40 bytes blob of
+/// extension, region and SHA-256 hash.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheHandle {
+    pub extension: String,
+    pub region: String,
+    pub hash: Vec<u8>,
+}
+
+impl CacheHandle {
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != 40 {
+            return None;
+        }
+        let text = |bytes: &[u8]| {
+            String::from_utf8_lossy(bytes)
+                .trim_matches(char::from(0))
+                .to_string()
+        };
+
+        Some(CacheHandle {
+            extension: text(&bytes[0..4]),
+            region: text(&bytes[4..8]),
+            hash: bytes[8..].to_vec(),
+        })
+    }
+
+    pub fn hash_hex(&self) -> String {
+        self.hash.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// URL of the file on the depot of its region.
+    pub fn url(&self) -> String {
+        format!(
+            "http://{}.depot.battle.net:1119/{}.{}",
+            self.region.to_lowercase(),
+            self.hash_hex(),
+            self.extension
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EventTypeVariant {
     GameEvent,
     MessageEvent,
     TrackerEvent,
+    /// Periodic game state checks, not described by the protocol.
+    SyncEvent,
+    /// Camera movements recorded for the smart camera, not described by the protocol.
+    SmartcamEvent,
+}
+
+impl EventTypeVariant {
+    /// Name of the archive file holding the stream.
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            EventTypeVariant::GameEvent => "replay.game.events",
+            EventTypeVariant::MessageEvent => "replay.message.events",
+            EventTypeVariant::TrackerEvent => "replay.tracker.events",
+            EventTypeVariant::SyncEvent => "replay.sync.events",
+            EventTypeVariant::SmartcamEvent => "replay.smartcam.events",
+        }
+    }
+}
+
+/// Failure to read a replay.
+#[derive(Debug)]
+pub enum ReplayError {
+    /// The replay could not be read, or is not a valid MPQ archive.
+    Io(io::Error),
+    /// The archive does not contain the file.
+    MissingFile(&'static str),
+    /// The content of the file is malformed for the protocol.
+    Decode {
+        file: &'static str,
+        error: DecodeError,
+    },
+    /// The stream has no known framing to decode events from, only its raw bytes can be read.
+    UndecodableStream(EventTypeVariant),
+    /// The protocol of the replay could not be loaded.
+    Protocol(String),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(error) => write!(f, "Failed to read archive: {error}"),
+            ReplayError::MissingFile(name) => write!(f, "Failed to find {name} in the archive"),
+            ReplayError::Decode { file, error } => write!(f, "Failed to decode {file}: {error}"),
+            ReplayError::UndecodableStream(variant) => write!(
+                f,
+                "Failed to decode {}, its framing is not known",
+                variant.file_name()
+            ),
+            ReplayError::Protocol(message) => write!(f, "Failed to load protocol: {message}"),
+        }
+    }
+}
+
+impl Error for ReplayError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ReplayError::Io(error) => Some(error),
+            ReplayError::Decode { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(error: io::Error) -> Self {
+        ReplayError::Io(error)
+    }
+}
+
+/// Decoded values built by hand for the tests of the modules reading them.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use super::*;
+
+    /// Struct of `fields`, the shape the decoders give to structs and choices.
+    pub fn fields(fields: Vec<(&str, ParsedFieldType)>) -> ParsedFieldType {
+        ParsedFieldType::Struct(
+            fields
+                .into_iter()
+                .map(|(name, value)| ParsedField {
+                    name: name.to_string(),
+                    value: Some(value),
+                })
+                .collect(),
+        )
+    }
+
+    /// Event of `user_id` at `game_loop` with the event data `data`.
+    pub fn event(
+        name: &str,
+        game_loop: usize,
+        user_id: Option<isize>,
+        data: Vec<(&str, ParsedFieldType)>,
+    ) -> Event {
+        Event {
+            name: name.to_string(),
+            event_id: 0,
+            game_loop,
+            user_id,
+            bits: 0,
+            data: ParsedField {
+                name: String::from("eventData"),
+                value: Some(fields(data)),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_converts_unit_tags() {
+        let tag = UnitTag::new(267, 1);
+        assert_eq!(u32::from(tag), 69992449);
+        assert_eq!(UnitTag::from(69992449), tag);
+        assert_eq!((tag.index(), tag.recycle()), (267, 1));
+        assert_eq!(UnitTag::try_new(267, 1), Some(tag));
+        assert_eq!(UnitTag::try_new(1 << 14, 1), None);
+        assert_eq!(UnitTag::try_new(267, 1 << 18), None);
+    }
 }
@@ -0,0 +1,308 @@
+use super::game_time::Timed;
+use super::types::*;
+use std::collections::{BTreeMap, HashMap};
+
+/// Unit types of the town halls the races start the game with.
+pub const STARTING_TOWN_HALLS: [&str; 3] = ["CommandCenter", "Nexus", "Hatchery"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeChange {
+    pub game_loop: usize,
+    pub unit_type: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OwnerChange {
+    pub game_loop: usize,
+    pub control_player_id: u8,
+    pub upkeep_player_id: u8,
+}
+
+/// Position of a unit in map cells, as reported by tracker events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnitPosition {
+    pub game_loop: usize,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl Timed for TypeChange {
+    fn game_loop(&self) -> usize {
+        self.game_loop
+    }
+}
+
+impl Timed for OwnerChange {
+    fn game_loop(&self) -> usize {
+        self.game_loop
+    }
+}
+
+impl Timed for UnitPosition {
+    fn game_loop(&self) -> usize {
+        self.game_loop
+    }
+}
+
+/// History of a single unit, from its creation to its death.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unit {
+    pub tag: UnitTag,
+    /// Types of the unit in order, starting with the one it was created as.
+    pub types: Vec<TypeChange>,
+    /// Owners of the unit in order, starting with the one it was created for. Owners whose
+    /// player ids do not fit a byte are skipped.
+    pub owners: Vec<OwnerChange>,
+    /// Loop of the `SUnitBornEvent` or, for buildings and morphs, the `SUnitInitEvent`.
+    pub born_loop: usize,
+    /// Loop at which the unit was completed, which is its birth for units born ready.
+    pub completed_loop: Option<usize>,
+    pub died_loop: Option<usize>,
+    pub positions: Vec<UnitPosition>,
+    pub killer_player_id: Option<u8>,
+    pub killer_tag: Option<UnitTag>,
+    pub creator_tag: Option<UnitTag>,
+    pub creator_ability: Option<String>,
+}
+
+impl Unit {
+    pub fn unit_type(&self) -> &str {
+        self.types
+            .last()
+            .map(|change| change.unit_type.as_str())
+            .unwrap_or_default()
+    }
+
+    pub fn control_player_id(&self) -> u8 {
+        self.owners
+            .last()
+            .map(|change| change.control_player_id)
+            .unwrap_or_default()
+    }
+
+    pub fn is_alive_at(&self, game_loop: usize) -> bool {
+        self.born_loop <= game_loop && self.died_loop.is_none_or(|died| game_loop < died)
+    }
+}
+
+/// Builds the history of every unit from the unit related tracker events.
+#[derive(Debug, Default)]
+pub struct UnitTracker {
+    units: BTreeMap<UnitTag, Unit>,
+    /// Tags of the living units by index, to resolve the indices of `SUnitPositionsEvent`.
+    alive: HashMap<u32, UnitTag>,
+}
+
+impl UnitTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_events(events: &[Event]) -> Self {
+        let mut tracker = Self::new();
+        for event in events {
+            tracker.process(event);
+        }
+        tracker
+    }
+
+    pub fn get(&self, tag: UnitTag) -> Option<&Unit> {
+        self.units.get(&tag)
+    }
+
+    /// Units ordered by tag.
+    pub fn units(&self) -> impl Iterator<Item = &Unit> {
+        self.units.values()
+    }
+
+    /// Town hall `player_id` started the game with, `None` for observers.
+    pub fn starting_town_hall(&self, player_id: u8) -> Option<&Unit> {
+        self.units().find(|unit| {
+            unit.born_loop == 0
+                && unit
+                    .owners
+                    .first()
+                    .is_some_and(|owner| owner.control_player_id == player_id)
+                && unit
+                    .types
+                    .first()
+                    .is_some_and(|change| STARTING_TOWN_HALLS.contains(&change.unit_type.as_str()))
+        })
+    }
+
+    pub fn into_units(self) -> BTreeMap<UnitTag, Unit> {
+        self.units
+    }
+
+    /// Updates the units with a tracker event, ignoring events which are not about units.
+    pub fn process(&mut self, event: &Event) {
+        let Some(data) = event.data.value.as_ref() else {
+            return;
+        };
+        let game_loop = event.game_loop;
+
+        match event.name.as_str() {
+            "NNet.Replay.Tracker.SUnitBornEvent" | "NNet.Replay.Tracker.SUnitInitEvent" => {
+                let Some(tag) = event.unit_tag() else {
+                    return;
+                };
+                let born = event.name == "NNet.Replay.Tracker.SUnitBornEvent";
+                self.alive.insert(tag.index(), tag);
+                self.units.insert(
+                    tag,
+                    Unit {
+                        tag,
+                        types: vec![TypeChange {
+                            game_loop,
+                            unit_type: string(data, "m_unitTypeName"),
+                        }],
+                        owners: owner_change(data, game_loop).into_iter().collect(),
+                        born_loop: game_loop,
+                        completed_loop: born.then_some(game_loop),
+                        died_loop: None,
+                        positions: vec![position(data, game_loop)],
+                        killer_player_id: None,
+                        killer_tag: None,
+                        creator_tag: UnitTag::from_fields(data, "m_creatorUnitTag"),
+                        creator_ability: data
+                            .field("m_creatorAbilityName")
+                            .and_then(ParsedFieldType::as_string),
+                    },
+                );
+            }
+            "NNet.Replay.Tracker.SUnitDoneEvent" => {
+                if let Some(unit) = self.unit_mut(event) {
+                    unit.completed_loop = Some(game_loop);
+                }
+            }
+            "NNet.Replay.Tracker.SUnitDiedEvent" => {
+                if let Some(unit) = self.unit_mut(event) {
+                    unit.died_loop = Some(game_loop);
+                    unit.positions.push(position(data, game_loop));
+                    unit.killer_player_id = data
+                        .field("m_killerPlayerId")
+                        .and_then(ParsedFieldType::as_int)
+                        .and_then(|id| u8::try_from(id).ok());
+                    unit.killer_tag = UnitTag::from_fields(data, "m_killerUnitTag");
+                    let tag = unit.tag;
+                    if self.alive.get(&tag.index()) == Some(&tag) {
+                        self.alive.remove(&tag.index());
+                    }
+                }
+            }
+            "NNet.Replay.Tracker.SUnitTypeChangeEvent" => {
+                if let Some(unit) = self.unit_mut(event) {
+                    unit.types.push(TypeChange {
+                        game_loop,
+                        unit_type: string(data, "m_unitTypeName"),
+                    });
+                }
+            }
+            "NNet.Replay.Tracker.SUnitOwnerChangeEvent" => {
+                if let Some(unit) = self.unit_mut(event) {
+                    unit.owners.extend(owner_change(data, game_loop));
+                }
+            }
+            "NNet.Replay.Tracker.SUnitPositionsEvent" => {
+                let items = data
+                    .field("m_items")
+                    .and_then(ParsedFieldType::as_array)
+                    .unwrap_or_default();
+                // Items are triplets of the index offset from the previous unit, x and y.
+                let mut index = int(data, "m_firstUnitIndex") as u32;
+                for item in items.chunks_exact(3) {
+                    index += item[0].as_int().unwrap_or_default() as u32;
+                    let Some(tag) = self.alive.get(&index) else {
+                        continue;
+                    };
+                    if let Some(unit) = self.units.get_mut(tag) {
+                        unit.positions.push(UnitPosition {
+                            game_loop,
+                            x: item[1].as_int().unwrap_or_default() as u32,
+                            y: item[2].as_int().unwrap_or_default() as u32,
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn unit_mut(&mut self, event: &Event) -> Option<&mut Unit> {
+        self.units.get_mut(&event.unit_tag()?)
+    }
+}
+
+/// Owners of a unit event, `None` when a player id is out of range.
+fn owner_change(data: &ParsedFieldType, game_loop: usize) -> Option<OwnerChange> {
+    Some(OwnerChange {
+        game_loop,
+        control_player_id: u8::try_from(int(data, "m_controlPlayerId")).ok()?,
+        upkeep_player_id: u8::try_from(int(data, "m_upkeepPlayerId")).ok()?,
+    })
+}
+
+fn position(data: &ParsedFieldType, game_loop: usize) -> UnitPosition {
+    UnitPosition {
+        game_loop,
+        x: int(data, "m_x") as u32,
+        y: int(data, "m_y") as u32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load_protocol_version;
+    use crate::replay::build_replay;
+    use crate::replay::types::fixtures::event;
+
+    #[test]
+    fn it_tracks_units_with_no_error() {
+        let protocol = load_protocol_version("93272");
+        let mut replay = build_replay("assets/replays/test.SC2Replay", &protocol);
+        let tracker = replay.units();
+        assert_eq!(tracker.units().count(), 355);
+
+        let command_center = tracker.get(UnitTag::new(196, 1)).unwrap();
+        assert_eq!(command_center.types[0].unit_type, "CommandCenter");
+        assert_eq!(command_center.unit_type(), "OrbitalCommand");
+        assert_eq!(command_center.types[1].game_loop, 2508);
+        assert_eq!(command_center.control_player_id(), 1);
+        assert_eq!(tracker.starting_town_hall(1), Some(command_center));
+        assert_eq!(tracker.starting_town_hall(2).unwrap().unit_type(), "Nexus");
+        assert_eq!(tracker.starting_town_hall(0), None);
+
+        let probe = tracker.get(UnitTag::new(239, 1)).unwrap();
+        assert_eq!(probe.unit_type(), "Probe");
+        assert_eq!(probe.creator_ability.as_deref(), Some("NexusTrain"));
+        assert_eq!(probe.creator_tag, Some(UnitTag::new(209, 1)));
+        assert_eq!((probe.born_loop, probe.died_loop), (1412, Some(6900)));
+        assert_eq!(probe.killer_player_id, Some(1));
+        assert_eq!(probe.killer_tag, Some(UnitTag::new(284, 1)));
+        assert!(probe.is_alive_at(6899) && !probe.is_alive_at(6900));
+
+        assert!(tracker.units().any(|unit| unit.positions.len() > 2));
+    }
+
+    #[test]
+    fn it_skips_owners_out_of_range() {
+        let born = |control_player_id| {
+            event(
+                "NNet.Replay.Tracker.SUnitBornEvent",
+                0,
+                None,
+                vec![
+                    ("m_unitTagIndex", ParsedFieldType::Int(196)),
+                    ("m_unitTagRecycle", ParsedFieldType::Int(1)),
+                    ("m_controlPlayerId", ParsedFieldType::Int(control_player_id)),
+                    ("m_upkeepPlayerId", ParsedFieldType::Int(1)),
+                ],
+            )
+        };
+        let tag = UnitTag::new(196, 1);
+        let owners = |tracker: &UnitTracker| tracker.get(tag).unwrap().owners.len();
+        assert_eq!(owners(&UnitTracker::from_events(&[born(1)])), 1);
+        assert_eq!(owners(&UnitTracker::from_events(&[born(257)])), 0);
+    }
+}
@@ -0,0 +1,134 @@
+use super::types::*;
+
+/// Callbacks driven by `Decoder::visit` while walking a value, without building a
+/// `ParsedField` tree. Every method defaults to a no-op so visitors only implement the
+/// ones they care about.
+pub trait Visitor {
+    fn visit_null(&mut self) {}
+    fn visit_bool(&mut self, _value: bool) {}
+    fn visit_int(&mut self, _value: isize) {}
+    fn visit_blob(&mut self, _value: &[u8]) {}
+    fn visit_bit_array(&mut self, _length: usize, _data: &[u8]) {}
+    fn visit_fourcc(&mut self, _value: &[u8]) {}
+    /// Called for an optional value that is absent.
+    fn visit_none(&mut self) {}
+    fn enter_array(&mut self, _length: usize) {}
+    fn exit_array(&mut self) {}
+    fn enter_struct(&mut self) {}
+    fn exit_struct(&mut self) {}
+    /// Called before each field of a struct is visited.
+    fn enter_field(&mut self, _name: &str) {}
+    fn exit_field(&mut self) {}
+    /// Called with the name of the selected variant before it is visited, the variant being
+    /// visited as a value rather than as a field.
+    fn enter_choice(&mut self, _variant: &str) {}
+    fn exit_choice(&mut self) {}
+}
+
+/// Longest array whose elements are allocated before being decoded.
+const MAX_PREALLOCATED_LENGTH: usize = 1024;
+
+enum Frame {
+    Array(Vec<ParsedFieldType>),
+    Struct(Vec<ParsedField>),
+}
+
+/// Visitor building the `ParsedFieldType` tree returned by `Decoder::instance`.
+#[derive(Default)]
+pub struct ParsedFieldBuilder {
+    frames: Vec<Frame>,
+    names: Vec<String>,
+    value: Option<ParsedFieldType>,
+}
+
+impl ParsedFieldBuilder {
+    pub fn finish(self) -> Option<ParsedFieldType> {
+        self.value
+    }
+
+    fn push(&mut self, value: Option<ParsedFieldType>) {
+        match self.frames.last_mut() {
+            // An absent optional element keeps its place in the array.
+            Some(Frame::Array(array)) => array.push(value.unwrap_or(ParsedFieldType::Null)),
+            Some(Frame::Struct(fields)) => fields.push(ParsedField {
+                name: self.names.last().cloned().unwrap_or_default(),
+                value,
+            }),
+            None => self.value = value,
+        }
+    }
+}
+
+impl Visitor for ParsedFieldBuilder {
+    fn visit_null(&mut self) {
+        self.push(Some(ParsedFieldType::Null));
+    }
+
+    fn visit_bool(&mut self, value: bool) {
+        self.push(Some(ParsedFieldType::Bool(value)));
+    }
+
+    fn visit_int(&mut self, value: isize) {
+        self.push(Some(ParsedFieldType::Int(value)));
+    }
+
+    fn visit_blob(&mut self, value: &[u8]) {
+        self.push(Some(ParsedFieldType::Blob(value.to_vec())));
+    }
+
+    fn visit_bit_array(&mut self, length: usize, data: &[u8]) {
+        let data = data.to_vec();
+        self.push(Some(ParsedFieldType::BitArray { length, data }));
+    }
+
+    fn visit_fourcc(&mut self, value: &[u8]) {
+        self.push(Some(ParsedFieldType::FourCC(value.to_vec())));
+    }
+
+    fn visit_none(&mut self) {
+        self.push(None);
+    }
+
+    fn enter_array(&mut self, length: usize) {
+        // The length is read from the data, so a malformed one must not allocate up front.
+        let capacity = length.min(MAX_PREALLOCATED_LENGTH);
+        self.frames.push(Frame::Array(Vec::with_capacity(capacity)));
+    }
+
+    fn exit_array(&mut self) {
+        if let Some(Frame::Array(array)) = self.frames.pop() {
+            self.push(Some(ParsedFieldType::Array(array)));
+        }
+    }
+
+    fn enter_struct(&mut self) {
+        self.frames.push(Frame::Struct(Vec::new()));
+    }
+
+    fn exit_struct(&mut self) {
+        if let Some(Frame::Struct(fields)) = self.frames.pop() {
+            self.push(Some(ParsedFieldType::Struct(fields)));
+        }
+    }
+
+    fn enter_field(&mut self, name: &str) {
+        self.names.push(name.to_string());
+    }
+
+    fn exit_field(&mut self) {
+        self.names.pop();
+    }
+
+    /// Choices become a struct with a single field named after the selected variant, as
+    /// s2protocol decodes them to a single key dict, so that variants of the same type stay
+    /// distinguishable. `ParsedFieldType::as_choice` reads them back.
+    fn enter_choice(&mut self, variant: &str) {
+        self.enter_struct();
+        self.enter_field(variant);
+    }
+
+    fn exit_choice(&mut self) {
+        self.exit_field();
+        self.exit_struct();
+    }
+}